@@ -30,7 +30,7 @@ fn criterion_benchmark(c: &mut Criterion) {
     group.finish();
 
     let g1 = "G1 X132.273 Y137.397 E3.64358";
-    let g1_args = vec![
+    let g1_args = [
         ('X', Some(132.273)),
         ('Y', Some(137.397)),
         ('E', Some(3.64358)),
@@ -48,7 +48,7 @@ fn criterion_benchmark(c: &mut Criterion) {
             assert_eq!(gcode.minor, 0);
 
             let mut args = gcode.arguments();
-            assert_eq!(args.next(), g1_args.get(0));
+            assert_eq!(args.next(), g1_args.first());
             assert_eq!(args.next(), g1_args.get(1));
             assert_eq!(args.next(), g1_args.get(2));
             assert_eq!(args.next(), None);
@@ -67,7 +67,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         assert_eq!(gcode.minor, 0);
 
         // let mut args = gcode.arguments();
-        // assert_eq!(args.next(), g1_args.get(0));
+        // assert_eq!(args.next(), g1_args.first());
         // assert_eq!(args.next(), g1_args.get(1));
         // assert_eq!(args.next(), g1_args.get(2));
         // assert_eq!(args.next(), None);
@@ -93,7 +93,7 @@ fn criterion_benchmark(c: &mut Criterion) {
                 }
             });
 
-        assert_eq!(args.next().as_ref(), g1_args.get(0));
+        assert_eq!(args.next().as_ref(), g1_args.first());
         assert_eq!(args.next().as_ref(), g1_args.get(1));
         assert_eq!(args.next().as_ref(), g1_args.get(2));
         assert_eq!(args.next(), None);
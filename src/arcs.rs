@@ -0,0 +1,462 @@
+use super::{
+    parse_lines,
+    parse_lines::line_ending,
+    rewrite::{rewrite_lines, round_value, with_comments},
+    toolpath::{arc_center, arc_sweep},
+    ArgOrComment,
+    Axis,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    MachineState,
+    Plane,
+    G,
+};
+
+/// Options for [linearize_arcs].
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArcLinearization {
+    /// The maximum distance in millimetres between an arc and the line segments replacing it.
+    pub chord_tolerance: f64,
+    /// The maximum length in millimetres of each line segment.
+    pub max_segment_length: f64,
+}
+
+impl Default for ArcLinearization {
+    fn default() -> Self {
+        Self {
+            chord_tolerance: 0.01,
+            max_segment_length: 1.0,
+        }
+    }
+}
+
+/// The maximum number of line segments an arc is replaced by, so that huge arcs (eg. from a
+/// typo in an offset) do not exhaust memory.
+pub const MAX_ARC_SEGMENTS: usize = 10_000;
+
+fn is_arc(gcode: &GCode) -> bool {
+    gcode.mnemonic == G && gcode.minor == 0 && (gcode.major == 2 || gcode.major == 3)
+}
+
+/// Replaces G2/G3 arcs with G1 line segments, for firmwares without arc support.
+///
+/// Arcs in any plane (G17/G18/G19) are supported, given either I/J/K offsets or a radius (R).
+/// Movement along the axis normal to the plane (ie. helical arcs) and extrusion are interpolated
+/// proportionally along each arc. The segments are written in the program's units and
+/// positioning modes so that the rest of the program is unaffected.
+///
+/// Arcs without a center (ie. without I/J/K offsets or a radius) are replaced by a straight G1
+/// move to their end, and each arc is replaced by at most [MAX_ARC_SEGMENTS] segments. Options
+/// that are not positive are rejected.
+pub fn linearize_arcs(
+    input: &str,
+    options: &ArcLinearization,
+) -> Result<String, GCodeParseError> {
+    // NaN options are rejected too
+    let is_positive = |value: f64| value > 0.0;
+
+    if !is_positive(options.chord_tolerance) || !is_positive(options.max_segment_length) {
+        return Err(GCodeParseError::InvalidOptions(format!("{:?}", options)));
+    }
+
+    rewrite_lines(input, |line, state| {
+        let gcode = match &line.gcode_line {
+            Some(GCodeLine::GCode(gcode)) if is_arc(gcode) => gcode,
+            _ => return None,
+        };
+
+        let points = linearize_arc(state, gcode, options);
+
+        Some(line_segments(state, gcode, line.text, &points))
+    })
+}
+
+/// The end points (in the active work coordinate system) of the line segments approximating an
+/// arc. Arcs missing their center are a single straight line.
+fn linearize_arc(
+    state: &MachineState,
+    gcode: &GCode,
+    options: &ArcLinearization,
+) -> Vec<[f64; 4]> {
+    let from = state.position;
+    let to = state.target(gcode);
+
+    let center = match arc_center(state, gcode, &from, &to) {
+        Some(center) => center,
+        None => return vec![to],
+    };
+
+    let [a, b, normal] = state.plane.axes();
+    let (a, b, normal, e) = (a.index(), b.index(), normal.index(), Axis::E.index());
+
+    let start_angle = (from[b] - center[b]).atan2(from[a] - center[a]);
+    let end_angle = (to[b] - center[b]).atan2(to[a] - center[a]);
+    let sweep = arc_sweep(start_angle, end_angle, gcode.major == 2);
+
+    // Inconsistent start and end radii are blended into a spiral rather than rejected
+    let start_radius = (from[a] - center[a]).hypot(from[b] - center[b]);
+    let end_radius = (to[a] - center[a]).hypot(to[b] - center[b]);
+    let max_radius = start_radius.max(end_radius);
+
+    let arc_length = sweep.abs() * (start_radius + end_radius) / 2.0;
+    let segments_for_length = (arc_length / options.max_segment_length).ceil();
+
+    // A chord spanning an angle θ deviates from the arc by r * (1 - cos(θ / 2))
+    let segments_for_tolerance = if options.chord_tolerance < max_radius {
+        let max_angle = 2.0 * (1.0 - options.chord_tolerance / max_radius).acos();
+
+        (sweep.abs() / max_angle).ceil()
+    } else {
+        1.0
+    };
+
+    let segment_count = segments_for_length
+        .max(segments_for_tolerance)
+        .clamp(1.0, MAX_ARC_SEGMENTS as f64) as usize;
+
+    (1..=segment_count)
+        .map(|i| {
+            if i == segment_count {
+                return to;
+            }
+
+            let t = i as f64 / segment_count as f64;
+            let angle = start_angle + sweep * t;
+            let radius = start_radius + (end_radius - start_radius) * t;
+
+            let mut point = from;
+            point[a] = center[a] + radius * angle.cos();
+            point[b] = center[b] + radius * angle.sin();
+            point[normal] = from[normal] + (to[normal] - from[normal]) * t;
+            point[e] = from[e] + (to[e] - from[e]) * t;
+
+            point
+        })
+        .collect()
+}
+
+/// Writes G1 moves to each point, in the program's units and positioning modes. Only axes that
+/// move during the arc are written, and the arc's comments are kept on the first move.
+fn line_segments(state: &MachineState, gcode: &GCode, text: &str, points: &[[f64; 4]]) -> String {
+    let from = state.position;
+    let to = points.last().copied().unwrap_or(from);
+
+    let moving_axes: Vec<Axis> = Axis::ALL
+        .iter()
+        .copied()
+        .filter(|axis| {
+            points.iter().any(|point| point[axis.index()] != from[axis.index()])
+                || to[axis.index()] != from[axis.index()]
+        })
+        .collect();
+
+    // Relative values are taken from rounded offsets to the start of the arc so that rounding
+    // errors do not accumulate
+    let mut previous_offsets = [0.0; 4];
+
+    let lines: Vec<String> = points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| {
+            let mut g1 = GCode::new(G, 1, 0);
+
+            for axis in moving_axes.iter().copied() {
+                let i = axis.index();
+
+                let value = if state.is_relative(axis) {
+                    let offset = round_value(state.units.from_millimeters(point[i] - from[i]));
+                    let value = offset - previous_offsets[i];
+
+                    previous_offsets[i] = offset;
+                    value
+                } else {
+                    state.units.from_millimeters(point[i])
+                };
+
+                g1.set_argument(axis.letter(), Some(round_value(value)));
+            }
+
+            if i > 0 {
+                return g1.to_string();
+            }
+
+            if let Some(feedrate) = gcode.value('F') {
+                g1.set_argument('F', Some(feedrate));
+            }
+
+            with_comments(&g1, text)
+        })
+        .collect();
+
+    lines.join("\n")
+}
+
+/// Options for [fit_arcs].
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArcFitting {
+    /// The maximum distance in millimetres between the original path and the arcs replacing it.
+    pub tolerance: f64,
+    /// The minimum number of G1 moves replaced by each arc.
+    pub min_moves: usize,
+    /// Arcs with a larger radius (in millimetres) are not fitted. Very large arcs are better
+    /// approximated by lines.
+    pub max_radius: f64,
+}
+
+impl Default for ArcFitting {
+    fn default() -> Self {
+        Self {
+            tolerance: 0.025,
+            min_moves: 3,
+            max_radius: 1000.0,
+        }
+    }
+}
+
+/// A G1 move that could be part of a fitted arc.
+#[derive(Debug, Clone)]
+struct ArcCandidate {
+    /// The position before and after the move, in the active work coordinate system.
+    from: [f64; 4],
+    to: [f64; 4],
+    feedrate: Option<f64>,
+}
+
+/// Finds G1 moves that could be part of a fitted arc: extruding XY moves without any other
+/// arguments.
+fn arc_candidate(state: &MachineState, gcode: &GCode) -> Option<ArcCandidate> {
+    let is_g1 = gcode.mnemonic == G && gcode.major == 1 && gcode.minor == 0;
+
+    let only_xyef = gcode.args_or_comments_iter().all(|ac| matches!(
+        ac,
+        ArgOrComment::KeyValue((key, Some(_))) if "XYEF".contains(key.to_ascii_uppercase()),
+    ));
+
+    if !is_g1 || !only_xyef || state.plane != Plane::XY {
+        return None;
+    }
+
+    let from = state.position;
+    let to = state.target(gcode);
+
+    let extrudes = to[Axis::E.index()] > from[Axis::E.index()];
+    let moves_xy = to[0] != from[0] || to[1] != from[1];
+
+    if !extrudes || !moves_xy {
+        return None;
+    }
+
+    Some(ArcCandidate {
+        from,
+        to,
+        feedrate: gcode.value('F'),
+    })
+}
+
+/// The center and radius of the circle through three XY points, or None if they are collinear.
+fn circle_through(p1: &[f64; 4], p2: &[f64; 4], p3: &[f64; 4]) -> Option<([f64; 2], f64)> {
+    let (bx, by) = (p2[0] - p1[0], p2[1] - p1[1]);
+    let (cx, cy) = (p3[0] - p1[0], p3[1] - p1[1]);
+
+    let d = 2.0 * (bx * cy - by * cx);
+
+    if d.abs() < 1e-12 {
+        return None;
+    }
+
+    let b_squared = bx * bx + by * by;
+    let c_squared = cx * cx + cy * cy;
+
+    let ux = (cy * b_squared - by * c_squared) / d;
+    let uy = (bx * c_squared - cx * b_squared) / d;
+
+    Some(([p1[0] + ux, p1[1] + uy], ux.hypot(uy)))
+}
+
+/// A fitted arc's center and whether it is clockwise.
+struct FittedArc {
+    center: [f64; 2],
+    clockwise: bool,
+}
+
+/// Fits an arc to a run of moves, or None if they do not lie on an arc within the tolerance.
+fn fit_arc(moves: &[ArcCandidate], options: &ArcFitting) -> Option<FittedArc> {
+    use std::f64::consts::PI;
+
+    let start = &moves[0].from;
+    let middle = &moves[moves.len() / 2].from;
+    let end = &moves[moves.len() - 1].to;
+
+    let (center, radius) = circle_through(start, middle, end)?;
+
+    if radius > options.max_radius {
+        return None;
+    }
+
+    let angle = |point: &[f64; 4]| (point[1] - center[1]).atan2(point[0] - center[0]);
+    let off_arc = |x: f64, y: f64| ((x - center[0]).hypot(y - center[1]) - radius).abs() > options.tolerance;
+
+    let mut sweep: f64 = 0.0;
+
+    for m in moves {
+        // Both the end points and the middle of each move must be on the arc
+        let (mid_x, mid_y) = ((m.from[0] + m.to[0]) / 2.0, (m.from[1] + m.to[1]) / 2.0);
+
+        if off_arc(m.to[0], m.to[1]) || off_arc(mid_x, mid_y) {
+            return None;
+        }
+
+        let mut step = angle(&m.to) - angle(&m.from);
+
+        if step > PI {
+            step -= 2.0 * PI;
+        } else if step < -PI {
+            step += 2.0 * PI;
+        }
+
+        // Every move must turn the same way
+        if sweep != 0.0 && step.signum() != sweep.signum() {
+            return None;
+        }
+
+        sweep += step;
+    }
+
+    // Full circles are ambiguous so arcs are kept under one revolution
+    if sweep == 0.0 || sweep.abs() >= 2.0 * PI - 1e-6 {
+        return None;
+    }
+
+    Some(FittedArc {
+        center,
+        clockwise: sweep < 0.0,
+    })
+}
+
+/// Writes a fitted arc replacing a run of moves, in the program's units and positioning modes.
+fn arc_gcode(state: &MachineState, moves: &[ArcCandidate], arc: &FittedArc) -> String {
+    let from = moves[0].from;
+    let to = moves[moves.len() - 1].to;
+
+    let mut gcode = GCode::new(G, if arc.clockwise { 2 } else { 3 }, 0);
+
+    for axis in [Axis::X, Axis::Y, Axis::E].iter().copied() {
+        let i = axis.index();
+
+        let value = if state.is_relative(axis) {
+            to[i] - from[i]
+        } else {
+            to[i]
+        };
+
+        gcode.set_argument(axis.letter(), Some(round_value(state.units.from_millimeters(value))));
+    }
+
+    gcode.set_argument('I', Some(round_value(state.units.from_millimeters(arc.center[0] - from[0]))));
+    gcode.set_argument('J', Some(round_value(state.units.from_millimeters(arc.center[1] - from[1]))));
+
+    if let Some(feedrate) = moves[0].feedrate {
+        gcode.set_argument('F', Some(feedrate));
+    }
+
+    gcode.to_string()
+}
+
+/// Writes a run of candidate moves, replacing them with arcs where they fit one.
+///
+/// `state` is the machine state before the run.
+fn write_run(
+    run: &[(&str, ArcCandidate)],
+    state: &MachineState,
+    options: &ArcFitting,
+    line_ending: &str,
+    output: &mut String,
+) {
+    let moves: Vec<ArcCandidate> = run.iter().map(|(_, m)| m.clone()).collect();
+    let mut start = 0;
+
+    while start < moves.len() {
+        // Greedily extend the arc for as long as the moves fit it
+        let mut arc = None;
+        let mut end = start + options.min_moves.max(2);
+
+        while end <= moves.len() {
+            match fit_arc(&moves[start..end], options) {
+                Some(fitted_arc) => arc = Some((end, fitted_arc)),
+                None => break,
+            }
+
+            end += 1;
+        }
+
+        match arc {
+            Some((end, arc)) => {
+                output.push_str(&arc_gcode(state, &moves[start..end], &arc));
+                start = end;
+            }
+            None => {
+                output.push_str(run[start].0);
+                start += 1;
+            }
+        }
+
+        output.push_str(line_ending);
+    }
+}
+
+/// Replaces runs of G1 moves that lie on a circular arc with G2/G3 arcs (like ArcWelder),
+/// reducing file size and serial bandwidth for curved prints.
+///
+/// Only extruding moves in the XY plane are fitted, and a run is broken by any other line or a
+/// feedrate change. The total extrusion of each replaced run is preserved.
+pub fn fit_arcs(input: &str, options: &ArcFitting) -> Result<String, GCodeParseError> {
+    let line_ending = line_ending(input);
+
+    let mut output = String::with_capacity(input.len());
+    let mut state = MachineState::new();
+
+    // The lines of the current run of candidate moves, and the state before the run
+    let mut run: Vec<(&str, ArcCandidate)> = vec![];
+    let mut run_state = state.clone();
+
+    for line in parse_lines(input) {
+        let line = line?;
+
+        let gcode = match &line.gcode_line {
+            Some(GCodeLine::GCode(gcode)) => Some(gcode),
+            _ => None,
+        };
+
+        match gcode.and_then(|gcode| arc_candidate(&state, gcode)) {
+            Some(candidate) => {
+                // Feedrate changes start a new run
+                if candidate.feedrate.is_some() && !run.is_empty() {
+                    write_run(&run, &run_state, options, line_ending, &mut output);
+                    run.clear();
+                }
+
+                if run.is_empty() {
+                    run_state = state.clone();
+                }
+
+                run.push((line.text, candidate));
+            }
+            None => {
+                write_run(&run, &run_state, options, line_ending, &mut output);
+                run.clear();
+
+                output.push_str(line.text);
+                output.push_str(line_ending);
+            }
+        }
+
+        if let Some(gcode) = gcode {
+            state.apply(gcode);
+        }
+    }
+
+    write_run(&run, &run_state, options, line_ending, &mut output);
+
+    Ok(output)
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+
+use super::{
+    toolpath,
+    Axis,
+    GCodeParseError,
+    SegmentKind,
+};
+
+/// An axis aligned box in X, Y and Z, in millimetres.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl BoundingBox {
+    /// A box containing a single point.
+    pub fn from_point(point: [f64; 3]) -> Self {
+        Self {
+            min: point,
+            max: point,
+        }
+    }
+
+    /// Grows the box to contain a point.
+    pub fn include(&mut self, point: [f64; 3]) {
+        for (i, value) in point.iter().copied().enumerate() {
+            self.min[i] = self.min[i].min(value);
+            self.max[i] = self.max[i].max(value);
+        }
+    }
+
+    /// Grows the box to contain another box.
+    pub fn union(&mut self, other: &BoundingBox) {
+        self.include(other.min);
+        self.include(other.max);
+    }
+
+    /// The width, depth and height of the box.
+    pub fn size(&self) -> [f64; 3] {
+        [
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        ]
+    }
+}
+
+/// Grows an optional box to contain another box.
+fn union(bounds: &mut Option<BoundingBox>, other: &BoundingBox) {
+    match bounds {
+        Some(bounds) => bounds.union(other),
+        None => *bounds = Some(*other),
+    }
+}
+
+/// The bounding boxes of a tool's moves. See [bounds].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ToolBounds {
+    /// The bounds of the moves that extrude, ie. of the printed part.
+    pub extruding: Option<BoundingBox>,
+    /// The bounds of every move, including travel moves.
+    pub all_moves: Option<BoundingBox>,
+}
+
+impl ToolBounds {
+    fn include(&mut self, segment_bounds: &BoundingBox, extruding: bool) {
+        if extruding {
+            union(&mut self.extruding, segment_bounds);
+        }
+
+        union(&mut self.all_moves, segment_bounds);
+    }
+}
+
+/// The bounding boxes of a program's moves, in machine coordinates. See [bounds].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Bounds {
+    /// The bounds of every tool's moves.
+    pub total: ToolBounds,
+    /// The bounds of each tool's moves, indexed by tool.
+    pub tools: BTreeMap<u32, ToolBounds>,
+}
+
+/// Calculates the bounding boxes of a program's moves, including the parts of arcs that bulge
+/// past their end points.
+pub fn bounds(input: &str) -> Result<Bounds, GCodeParseError> {
+    let mut bounds = Bounds::default();
+
+    for segment in toolpath(input) {
+        let segment = segment?;
+
+        let segment_bounds = segment.bounding_box();
+        let extruding = segment.extrusion > 0.0 && segment.kind != SegmentKind::Travel;
+
+        bounds.total.include(&segment_bounds, extruding);
+        bounds.tools
+            .entry(segment.tool)
+            .or_default()
+            .include(&segment_bounds, extruding);
+    }
+
+    Ok(bounds)
+}
+
+/// The volume a machine can move within, in machine coordinates in millimetres.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MachineEnvelope {
+    /// The minimum X, Y and Z positions (eg. negative soft limits for a nozzle that can park off
+    /// the bed).
+    pub min: [f64; 3],
+    /// The maximum X, Y and Z positions.
+    pub max: [f64; 3],
+}
+
+impl MachineEnvelope {
+    /// An envelope from the origin to the bed size and maximum Z height.
+    pub fn new(bed_width: f64, bed_depth: f64, max_z: f64) -> Self {
+        Self {
+            min: [0.0; 3],
+            max: [bed_width, bed_depth, max_z],
+        }
+    }
+}
+
+/// A move that leaves the [MachineEnvelope].
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnvelopeViolation {
+    /// The 1-based line number of the move.
+    pub source_line: usize,
+    pub axis: Axis,
+    /// The furthest position the move reaches outside the envelope.
+    pub position: f64,
+    /// The limit of the envelope that was exceeded.
+    pub limit: f64,
+}
+
+/// Checks that every move of a program stays within a machine envelope, returning the moves
+/// that do not.
+pub fn check_envelope(
+    input: &str,
+    envelope: &MachineEnvelope,
+) -> Result<Vec<EnvelopeViolation>, GCodeParseError> {
+    let mut violations = vec![];
+
+    for segment in toolpath(input) {
+        let segment = segment?;
+        let segment_bounds = segment.bounding_box();
+
+        for axis in [Axis::X, Axis::Y, Axis::Z].iter().copied() {
+            let i = axis.index();
+
+            if segment_bounds.min[i] < envelope.min[i] {
+                violations.push(EnvelopeViolation {
+                    source_line: segment.source_line,
+                    axis,
+                    position: segment_bounds.min[i],
+                    limit: envelope.min[i],
+                });
+            }
+
+            if segment_bounds.max[i] > envelope.max[i] {
+                violations.push(EnvelopeViolation {
+                    source_line: segment.source_line,
+                    axis,
+                    position: segment_bounds.max[i],
+                    limit: envelope.max[i],
+                });
+            }
+        }
+    }
+
+    Ok(violations)
+}
@@ -0,0 +1,188 @@
+use alloc::collections::BTreeMap;
+
+use super::{
+    rewrite::{rewrite_lines, round_value},
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    LayerIndex,
+    LayerSelector,
+    MachineState,
+    Mnemonic::*,
+};
+
+/// A setting varied by a calibration tower. See [ParameterSweep].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CalibrationParameter {
+    /// The active hotend's temperature in °C (M104 S). The temperature is set without waiting so
+    /// the tower keeps printing while it changes.
+    HotendTemperature,
+    /// The flow rate percentage (M221 S).
+    FlowRate,
+    /// The speed factor percentage (M220 S).
+    SpeedFactor,
+    /// Marlin's linear advance K factor (M900 K).
+    LinearAdvance,
+    /// Klipper's pressure advance (SET_PRESSURE_ADVANCE ADVANCE=).
+    PressureAdvance,
+}
+
+impl CalibrationParameter {
+    /// The command setting the parameter to a value.
+    pub fn command(self, value: f64) -> String {
+        let value = round_value(value);
+
+        let (major, letter) = match self {
+            Self::HotendTemperature => (104, 'S'),
+            Self::FlowRate => (221, 'S'),
+            Self::SpeedFactor => (220, 'S'),
+            Self::LinearAdvance => (900, 'K'),
+            Self::PressureAdvance => return format!("SET_PRESSURE_ADVANCE ADVANCE={}", value),
+        };
+
+        GCode::new(Miscellaneous, major, 0)
+            .with_argument(letter, Some(value))
+            .to_string()
+    }
+
+    /// Whether a command sets the parameter, and so would override a sweep.
+    fn is_set_by(self, gcode_line: &GCodeLine, state: &MachineState) -> bool {
+        let gcode = match gcode_line {
+            GCodeLine::GCode(gcode) => gcode,
+            GCodeLine::ExtendedCommand(command) => {
+                return self == Self::PressureAdvance
+                    && command.name.eq_ignore_ascii_case("SET_PRESSURE_ADVANCE")
+                    && command.argument("ADVANCE").is_some();
+            }
+            _ => return false,
+        };
+
+        match (self, gcode.mnemonic, gcode.major, gcode.minor) {
+            // M109 is removed too rather than waiting for the temperature the sweep replaces
+            (Self::HotendTemperature, Miscellaneous, 104, 0)
+            | (Self::HotendTemperature, Miscellaneous, 109, 0) => {
+                let is_active_tool = gcode
+                    .value('T')
+                    .map(|tool| tool as u32 == state.tool)
+                    .unwrap_or(true);
+
+                is_active_tool && gcode.argument('S').is_some()
+            }
+            (Self::FlowRate, Miscellaneous, 221, 0)
+            | (Self::SpeedFactor, Miscellaneous, 220, 0) => gcode.argument('S').is_some(),
+            (Self::LinearAdvance, Miscellaneous, 900, 0) => gcode.argument('K').is_some(),
+            _ => false,
+        }
+    }
+}
+
+/// A value of a [ParameterSweep], used from the start of a layer until the next step.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SweepStep {
+    pub layer: LayerSelector,
+    pub value: f64,
+}
+
+/// Changes of a parameter at layers, eg. to turn an ordinary sliced tower into a temperature
+/// tower. See [apply_parameter_sweep].
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParameterSweep {
+    pub parameter: CalibrationParameter,
+    pub steps: Vec<SweepStep>,
+}
+
+impl ParameterSweep {
+    pub fn new(parameter: CalibrationParameter) -> Self {
+        Self {
+            parameter,
+            steps: vec![],
+        }
+    }
+
+    pub fn with_step(mut self, layer: LayerSelector, value: f64) -> Self {
+        self.steps.push(SweepStep { layer, value });
+        self
+    }
+
+    /// A tower of `sections` equal height sections starting at `start_z` (in millimetres), the
+    /// value changing by `value_step` from `start_value` at each section.
+    ///
+    /// For example a temperature tower from 220 °C to 190 °C every 5mm is
+    /// `ParameterSweep::tower(CalibrationParameter::HotendTemperature, 0.0, 5.0, 220.0, -5.0, 7)`.
+    pub fn tower(
+        parameter: CalibrationParameter,
+        start_z: f64,
+        section_height: f64,
+        start_value: f64,
+        value_step: f64,
+        sections: usize,
+    ) -> Self {
+        let steps = (0..sections)
+            .map(|section| {
+                let section = section as f64;
+
+                SweepStep {
+                    layer: LayerSelector::Z(start_z + section * section_height),
+                    value: start_value + section * value_step,
+                }
+            })
+            .collect();
+
+        Self { parameter, steps }
+    }
+}
+
+/// Applies a parameter sweep to a sliced file, producing a calibration tower. Each step's
+/// command is inserted at the start of its layer (see [crate::insert_at_layers]), and steps
+/// whose layer does not exist are skipped.
+///
+/// Commands already setting the parameter from the first step onwards (eg. the slicer's change
+/// from the first layer temperature, or a `SET_PRESSURE_ADVANCE` in a Klipper macro's output)
+/// are removed so that they do not override the sweep. Other extended commands (eg.
+/// `PRINT_START`) are copied unchanged.
+pub fn apply_parameter_sweep(
+    input: &str,
+    sweep: &ParameterSweep,
+) -> Result<String, GCodeParseError> {
+    let index = LayerIndex::new(input)?;
+
+    // The commands to insert by the byte offset of the line they are inserted before
+    let mut insertions: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+
+    for step in &sweep.steps {
+        if let Some(layer) = index.find(step.layer) {
+            insertions
+                .entry(layer.span.start)
+                .or_default()
+                .push(sweep.parameter.command(step.value));
+        }
+    }
+
+    let sweep_start = match insertions.keys().next() {
+        Some(offset) => *offset,
+        None => return Ok(input.to_string()),
+    };
+
+    rewrite_lines(input, |line, state| {
+        let is_overridden = match &line.gcode_line {
+            Some(gcode_line) => {
+                line.span.start >= sweep_start && sweep.parameter.is_set_by(gcode_line, state)
+            }
+            None => false,
+        };
+
+        let commands = insertions.remove(&line.span.start);
+
+        if commands.is_none() && !is_overridden {
+            return None;
+        }
+
+        let mut lines = commands.unwrap_or_default();
+
+        if !is_overridden {
+            lines.push(line.text.to_string());
+        }
+
+        Some(lines.join("\n"))
+    })
+}
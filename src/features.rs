@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::{
+    parse_lines,
+    print_time::PrintTimePlanner,
+    DocComment,
+    GCodeLine,
+    GCodeParseError,
+    MotionLimits,
+};
+
+/// The role of a printed feature, normalized from the names different slicers use in their
+/// `;TYPE:` comments.
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord, Hash)]
+pub enum FeatureRole {
+    ExternalPerimeter,
+    Perimeter,
+    OverhangPerimeter,
+    Infill,
+    SolidInfill,
+    TopSolidInfill,
+    BridgeInfill,
+    GapFill,
+    Skirt,
+    Support,
+    SupportInterface,
+    /// A prime or wipe tower.
+    PrimeTower,
+    /// Moves from the slicer's custom (eg. start or end) GCode.
+    Custom,
+    /// A feature type the crate does not recognize, with the slicer's name for it.
+    Other(String),
+}
+
+impl FeatureRole {
+    /// Normalizes a Cura, PrusaSlicer, SuperSlicer, OrcaSlicer or Bambu Studio feature type (eg.
+    /// "WALL-OUTER" or "External perimeter").
+    pub fn from_type_name(name: &str) -> Self {
+        match name.trim() {
+            // Cura
+            "WALL-OUTER" => FeatureRole::ExternalPerimeter,
+            "WALL-INNER" => FeatureRole::Perimeter,
+            "FILL" => FeatureRole::Infill,
+            "SKIN" => FeatureRole::SolidInfill,
+            "SKIRT" => FeatureRole::Skirt,
+            "SUPPORT" => FeatureRole::Support,
+            "SUPPORT-INTERFACE" => FeatureRole::SupportInterface,
+            "PRIME-TOWER" => FeatureRole::PrimeTower,
+            // PrusaSlicer and its forks
+            "External perimeter" | "Outer wall" => FeatureRole::ExternalPerimeter,
+            "Perimeter" | "Inner wall" => FeatureRole::Perimeter,
+            "Overhang perimeter" | "Overhang wall" => FeatureRole::OverhangPerimeter,
+            "Internal infill" | "Sparse infill" => FeatureRole::Infill,
+            "Solid infill" | "Internal solid infill" | "Bottom surface" => FeatureRole::SolidInfill,
+            "Top solid infill" | "Top surface" => FeatureRole::TopSolidInfill,
+            "Bridge infill" | "Internal bridge infill" | "Bridge" => FeatureRole::BridgeInfill,
+            "Gap fill" | "Gap infill" => FeatureRole::GapFill,
+            "Skirt" | "Skirt/Brim" | "Brim" => FeatureRole::Skirt,
+            "Support material" | "Support" => FeatureRole::Support,
+            "Support material interface" | "Support interface" => FeatureRole::SupportInterface,
+            "Wipe tower" | "Prime tower" => FeatureRole::PrimeTower,
+            "Custom" => FeatureRole::Custom,
+            name => FeatureRole::Other(name.to_string()),
+        }
+    }
+}
+
+/// The filament and time spent on a feature.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FeatureStats {
+    /// The length of filament extruded in millimetres, not counting retractions.
+    pub extrusion: f64,
+    /// The estimated time spent on the feature's moves.
+    pub duration: Duration,
+}
+
+/// The filament and time spent on each feature of a program. See [feature_statistics].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FeatureStatistics {
+    pub features: BTreeMap<FeatureRole, FeatureStats>,
+    /// The moves before the first `;TYPE:` comment (eg. start GCode).
+    pub untagged: FeatureStats,
+}
+
+/// The statistics of a feature (by its index in `features`), or of the untagged moves.
+fn stats_mut<'a>(
+    features: &'a mut [(FeatureRole, FeatureStats)],
+    untagged: &'a mut FeatureStats,
+    feature: Option<usize>,
+) -> &'a mut FeatureStats {
+    match feature {
+        Some(index) => &mut features[index].1,
+        None => untagged,
+    }
+}
+
+/// Calculates the filament extruded and the estimated time spent on each feature of a program,
+/// as tagged by the slicer's `;TYPE:` comments. See [crate::estimate_print_time].
+pub fn feature_statistics(
+    input: &str,
+    limits: &MotionLimits,
+) -> Result<FeatureStatistics, GCodeParseError> {
+    let mut planner = PrintTimePlanner::new(limits);
+
+    // Features are referred to by their index here so that they are not cloned for every line
+    let mut features: Vec<(FeatureRole, FeatureStats)> = vec![];
+    let mut untagged = FeatureStats::default();
+    let mut feature = None;
+
+    // The feature of each line, by 1-based line number, including dwells
+    let mut line_features = vec![None];
+
+    for line in parse_lines(input) {
+        let line = line?;
+
+        match &line.gcode_line {
+            Some(GCodeLine::DocComment(DocComment::FeatureType(name))) => {
+                let role = FeatureRole::from_type_name(name);
+
+                let index = match features.iter().position(|(existing, _)| *existing == role) {
+                    Some(index) => index,
+                    None => {
+                        features.push((role, FeatureStats::default()));
+                        features.len() - 1
+                    }
+                };
+
+                feature = Some(index);
+            }
+            Some(GCodeLine::GCode(gcode)) => {
+                let segment = planner.push(gcode, line.number);
+
+                if let Some(segment) = segment.filter(|segment| segment.extrusion > 0.0) {
+                    stats_mut(&mut features, &mut untagged, feature).extrusion += segment.extrusion;
+                }
+            }
+            _ => {}
+        }
+
+        line_features.push(feature);
+    }
+
+    let mut previous_time = Duration::default();
+
+    for (line_number, time) in planner.finish().line_times {
+        stats_mut(&mut features, &mut untagged, line_features[line_number]).duration +=
+            time - previous_time;
+        previous_time = time;
+    }
+
+    Ok(FeatureStatistics {
+        features: features.into_iter().collect(),
+        untagged,
+    })
+}
@@ -0,0 +1,173 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::f64::consts::PI;
+
+use super::{
+    parse_lines,
+    toolpath::apply_move,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    MachineState,
+    Mnemonic::*,
+};
+
+/// The filament used by an extruder. Lengths are in millimetres of filament.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ExtruderUsage {
+    /// The total length of filament pushed into the hotend, including recovering from
+    /// retractions.
+    pub extruded: f64,
+    /// The total length of filament pulled back by retractions.
+    pub retracted: f64,
+    /// The number of retractions, including firmware retractions (G10).
+    pub retractions: u32,
+}
+
+impl ExtruderUsage {
+    /// The length of filament consumed.
+    pub fn length(&self) -> f64 {
+        self.extruded - self.retracted
+    }
+
+    /// The volume of filament consumed in mm³, given the filament diameter in millimetres.
+    pub fn volume(&self, diameter: f64) -> f64 {
+        self.length() * PI * (diameter / 2.0).powi(2)
+    }
+
+    /// The mass of filament consumed in grams, given the filament diameter in millimetres and its
+    /// density in g/cm³.
+    pub fn mass(&self, diameter: f64, density: f64) -> f64 {
+        self.volume(diameter) / 1000.0 * density
+    }
+}
+
+/// Firmware retraction settings, updated by M207 and M208 in the program. Lengths are in
+/// millimetres of filament and the defaults are Marlin's defaults.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FirmwareRetraction {
+    /// The length retracted by G10 (M207 S).
+    pub length: f64,
+    /// The length retracted by G10 S1 before a tool change (M207 W).
+    pub swap_length: f64,
+    /// The extra length recovered by G11 (M208 S).
+    pub extra_recover_length: f64,
+    /// The extra length recovered by G11 after a tool change (M208 W).
+    pub swap_extra_recover_length: f64,
+}
+
+impl Default for FirmwareRetraction {
+    fn default() -> Self {
+        Self {
+            length: 3.0,
+            swap_length: 13.0,
+            extra_recover_length: 0.0,
+            swap_extra_recover_length: 0.0,
+        }
+    }
+}
+
+/// The filament used by each extruder of a program. See [filament_usage].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FilamentUsage {
+    /// The filament used by each extruder, indexed by tool.
+    pub extruders: BTreeMap<u32, ExtruderUsage>,
+}
+
+impl FilamentUsage {
+    /// The length of filament consumed by all extruders in millimetres.
+    pub fn length(&self) -> f64 {
+        self.extruders.values().map(ExtruderUsage::length).sum()
+    }
+}
+
+/// Calculates the filament used by a program from its extrusion moves rather than trusting the
+/// slicer's comments.
+///
+/// Absolute and relative extrusion (M82/M83), extruder position resets (G92 E) and firmware
+/// retraction (G10/G11) are taken into account.
+pub fn filament_usage(
+    input: &str,
+    firmware_retraction: &FirmwareRetraction,
+) -> Result<FilamentUsage, GCodeParseError> {
+    let mut retraction = firmware_retraction.clone();
+    let mut state = MachineState::new();
+    let mut usage = FilamentUsage::default();
+
+    // The length of filament each extruder has retracted with G10 and will recover with G11, and
+    // whether it was a tool change (swap) retraction
+    let mut firmware_retracted: BTreeMap<u32, (f64, bool)> = BTreeMap::new();
+
+    for line in parse_lines(input) {
+        let line = line?;
+
+        let gcode = match &line.gcode_line {
+            Some(GCodeLine::GCode(gcode)) => gcode,
+            _ => continue,
+        };
+
+        match (gcode.mnemonic, gcode.major, gcode.minor) {
+            (Miscellaneous, 207, 0) => {
+                set_if_present(gcode, 'S', &mut retraction.length);
+                set_if_present(gcode, 'W', &mut retraction.swap_length);
+            }
+            (Miscellaneous, 208, 0) => {
+                set_if_present(gcode, 'S', &mut retraction.extra_recover_length);
+                set_if_present(gcode, 'W', &mut retraction.swap_extra_recover_length);
+            }
+            // G10 with an L argument sets coordinate system offsets instead of retracting
+            (General, 10, 0) if gcode.argument('L').is_none() => {
+                let tool = state.tool;
+
+                // Repeated G10s are ignored until the filament is recovered
+                if let Entry::Vacant(entry) = firmware_retracted.entry(tool) {
+                    let swap = gcode.value('S') == Some(1.0);
+
+                    let length = if swap {
+                        retraction.swap_length
+                    } else {
+                        retraction.length
+                    };
+
+                    let extruder = usage.extruders.entry(tool).or_default();
+                    extruder.retracted += length;
+                    extruder.retractions += 1;
+
+                    entry.insert((length, swap));
+                }
+            }
+            (General, 11, 0) => {
+                let tool = state.tool;
+
+                if let Some((length, swap)) = firmware_retracted.remove(&tool) {
+                    let extra = if swap {
+                        retraction.swap_extra_recover_length
+                    } else {
+                        retraction.extra_recover_length
+                    };
+
+                    usage.extruders.entry(tool).or_default().extruded += length + extra;
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(segment) = apply_move(&mut state, gcode, line.number) {
+            let extruder = usage.extruders.entry(segment.tool).or_default();
+
+            if segment.extrusion > 0.0 {
+                extruder.extruded += segment.extrusion;
+            } else if segment.extrusion < 0.0 {
+                extruder.retracted -= segment.extrusion;
+                extruder.retractions += 1;
+            }
+        }
+    }
+
+    Ok(usage)
+}
+
+fn set_if_present(gcode: &GCode, key: char, value: &mut f64) {
+    if let Some(new_value) = gcode.value(key) {
+        *value = new_value;
+    }
+}
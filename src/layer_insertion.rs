@@ -0,0 +1,66 @@
+use super::{
+    parse_lines::line_ending,
+    GCodeParseError,
+    LayerIndex,
+    LayerSelector,
+};
+
+/// GCode to insert at the start of a layer. See [insert_at_layers].
+#[derive(Debug, PartialEq, Clone)]
+pub struct LayerInsertion<'a> {
+    pub layer: LayerSelector,
+    /// The GCode to insert, which may span several lines (eg. a park and pause sequence).
+    pub gcode: &'a str,
+}
+
+impl<'a> LayerInsertion<'a> {
+    pub fn new(layer: LayerSelector, gcode: &'a str) -> Self {
+        Self {
+            layer,
+            gcode,
+        }
+    }
+}
+
+/// Inserts GCode (eg. an `M600` filament change or an `M0` pause) at the start of layers, before
+/// the move to the layer's height. Layers are found as described by [LayerIndex].
+///
+/// Insertions at the same layer are written in the order given. Insertions whose layer does not
+/// exist are skipped, so [LayerIndex::find] can be used to check them beforehand. The input's
+/// line endings are preserved.
+pub fn insert_at_layers(
+    input: &str,
+    insertions: &[LayerInsertion<'_>],
+) -> Result<String, GCodeParseError> {
+    let index = LayerIndex::new(input)?;
+    let line_ending = line_ending(input);
+
+    let mut offsets: Vec<(usize, &str)> = insertions
+        .iter()
+        .filter_map(|insertion| {
+            let layer = index.find(insertion.layer)?;
+
+            Some((layer.span.start, insertion.gcode))
+        })
+        .collect();
+
+    // A stable sort keeps insertions at the same layer in order
+    offsets.sort_by_key(|(offset, _)| *offset);
+
+    let mut output = String::with_capacity(input.len());
+    let mut copied = 0;
+
+    for (offset, gcode) in offsets {
+        output.push_str(&input[copied..offset]);
+        copied = offset;
+
+        for line in gcode.lines() {
+            output.push_str(line);
+            output.push_str(line_ending);
+        }
+    }
+
+    output.push_str(&input[copied..]);
+
+    Ok(output)
+}
@@ -0,0 +1,236 @@
+use core::ops::Range;
+
+use super::{
+    parse_lines,
+    toolpath::apply_move,
+    DocComment,
+    GCodeLine,
+    GCodeParseError,
+    MachineState,
+    SegmentKind,
+};
+
+/// Z heights closer than this (in millimetres) are considered the same layer.
+const Z_TOLERANCE: f64 = 1e-6;
+
+/// A layer of a print. See [LayerIndex].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Layer {
+    /// The slicer's number for the layer (eg. 3 for `;LAYER:3`), if it has one.
+    pub number: Option<i32>,
+    /// The Z height of the layer in millimetres.
+    pub z: f64,
+    /// The thickness of the layer in millimetres.
+    pub thickness: f64,
+    /// The byte range of the layer in the input, up to the start of the next layer.
+    pub span: Range<usize>,
+    /// The 1-based line numbers of the layer, up to the first line of the next layer.
+    pub lines: Range<usize>,
+}
+
+/// The layers of a print, in the order they are printed.
+///
+/// Layers start at slicer layer markers (`;LAYER:`, `;LAYER_CHANGE`) when the file has any,
+/// taking their Z height and thickness from `;Z:` and `;HEIGHT:` comments when present.
+/// Otherwise a layer starts at the Z move before the first extrusion at a new height, so Z hops
+/// do not create layers.
+///
+/// Lines before the first layer (eg. start GCode) are not part of any layer, and lines after the
+/// last layer (eg. end GCode) are part of the last layer.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LayerIndex {
+    pub layers: Vec<Layer>,
+}
+
+/// Selects a layer of a [LayerIndex].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LayerSelector {
+    /// The 0-based index of the layer in print order.
+    Index(usize),
+    /// The slicer's number for the layer (eg. 3 for `;LAYER:3`).
+    Number(i32),
+    /// The first layer at or above a Z height in millimetres.
+    Z(f64),
+}
+
+/// A layer whose Z height and thickness are not known yet.
+struct PendingLayer {
+    number: Option<i32>,
+    z: Option<f64>,
+    thickness: Option<f64>,
+    start: usize,
+    start_line: usize,
+    has_moves: bool,
+}
+
+impl PendingLayer {
+    fn new(start: usize, start_line: usize) -> Self {
+        Self {
+            number: None,
+            z: None,
+            thickness: None,
+            start,
+            start_line,
+            has_moves: false,
+        }
+    }
+}
+
+impl LayerIndex {
+    /// Indexes the layers of a GCode file.
+    pub fn new(input: &str) -> Result<Self, GCodeParseError> {
+        let mut state = MachineState::new();
+
+        let mut marker_layers: Vec<PendingLayer> = vec![];
+        let mut z_layers: Vec<PendingLayer> = vec![];
+
+        // The start of the most recent move that changed Z
+        let mut z_change: Option<(usize, usize)> = None;
+        let mut line_count = 0;
+
+        for line in parse_lines(input) {
+            let line = line?;
+            line_count = line.number;
+
+            let gcode = match &line.gcode_line {
+                Some(GCodeLine::DocComment(doc)) => {
+                    match doc {
+                        DocComment::Layer(number) => {
+                            start_marker_layer(&mut marker_layers, line.span.start, line.number);
+                            marker_layers.last_mut().unwrap().number = Some(*number);
+                        }
+                        DocComment::LayerChange => {
+                            start_marker_layer(&mut marker_layers, line.span.start, line.number);
+                        }
+                        DocComment::LayerZ { millis } => {
+                            if let Some(layer) = marker_layers.last_mut() {
+                                layer.z = Some(*millis);
+                            }
+                        }
+                        DocComment::LayerThickness { millis } => {
+                            if let Some(layer) = marker_layers.last_mut() {
+                                layer.thickness = Some(*millis);
+                            }
+                        }
+                        _ => {}
+                    }
+
+                    continue;
+                }
+                Some(GCodeLine::GCode(gcode)) => gcode,
+                _ => continue,
+            };
+
+            let segment = match apply_move(&mut state, gcode, line.number) {
+                Some(segment) => segment,
+                None => continue,
+            };
+
+            if let Some(layer) = marker_layers.last_mut() {
+                layer.has_moves = true;
+            }
+
+            let z = segment.to[2];
+
+            if (z - segment.from[2]).abs() > Z_TOLERANCE {
+                z_change = Some((line.span.start, line.number));
+            }
+
+            if segment.kind == SegmentKind::Travel {
+                continue;
+            }
+
+            // Marker layers without a ;Z: comment are at the height of their first extrusion
+            if let Some(layer) = marker_layers.last_mut() {
+                layer.z.get_or_insert(z);
+            }
+
+            let is_new_height = z_layers
+                .last()
+                .and_then(|layer| layer.z)
+                .map(|layer_z| (z - layer_z).abs() > Z_TOLERANCE)
+                .unwrap_or(true);
+
+            if is_new_height {
+                let (start, start_line) = z_change
+                    .filter(|(start, _)| {
+                        z_layers.last().map(|layer| *start > layer.start).unwrap_or(true)
+                    })
+                    .unwrap_or((line.span.start, line.number));
+
+                let mut layer = PendingLayer::new(start, start_line);
+                layer.z = Some(z);
+
+                z_layers.push(layer);
+            }
+        }
+
+        let pending_layers = if marker_layers.is_empty() {
+            z_layers
+        } else {
+            marker_layers
+        };
+
+        let mut layers: Vec<Layer> = vec![];
+        let mut pending_layers = pending_layers.into_iter().peekable();
+
+        while let Some(pending) = pending_layers.next() {
+            let (end, end_line) = pending_layers
+                .peek()
+                .map(|next| (next.start, next.start_line))
+                .unwrap_or((input.len(), line_count + 1));
+
+            let previous_z = layers.last().map(|layer| layer.z);
+            let z = pending.z.or(previous_z).unwrap_or(0.0);
+
+            layers.push(Layer {
+                number: pending.number,
+                z,
+                thickness: pending.thickness.unwrap_or(z - previous_z.unwrap_or(0.0)),
+                span: pending.start..end,
+                lines: pending.start_line..end_line,
+            });
+        }
+
+        Ok(Self { layers })
+    }
+
+    /// The layer chosen by a selector.
+    pub fn find(&self, selector: LayerSelector) -> Option<&Layer> {
+        match selector {
+            LayerSelector::Index(index) => self.layers.get(index),
+            LayerSelector::Number(number) => self.layer(number),
+            LayerSelector::Z(z) => self.layers.iter().find(|layer| layer.z >= z - Z_TOLERANCE),
+        }
+    }
+
+    /// The layer the slicer numbered `number` (eg. 3 for `;LAYER:3`).
+    pub fn layer(&self, number: i32) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.number == Some(number))
+    }
+
+    /// The first layer at the given Z height in millimetres.
+    pub fn layer_at_z(&self, z: f64) -> Option<&Layer> {
+        self.layers.iter().find(|layer| (layer.z - z).abs() <= Z_TOLERANCE)
+    }
+
+    /// The layer containing a 1-based line number.
+    pub fn layer_containing_line(&self, line_number: usize) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.lines.contains(&line_number))
+    }
+
+    /// The layer containing a byte offset into the input.
+    pub fn layer_containing_offset(&self, offset: usize) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.span.contains(&offset))
+    }
+}
+
+/// Starts a layer at a slicer marker unless the previous marker started a layer that has no
+/// moves yet (eg. PrusaSlicer's `;LAYER_CHANGE` followed by a custom `;LAYER:3`).
+fn start_marker_layer(layers: &mut Vec<PendingLayer>, start: usize, start_line: usize) {
+    let is_same_layer = layers.last().map(|layer| !layer.has_moves).unwrap_or(false);
+
+    if !is_same_layer {
+        layers.push(PendingLayer::new(start, start_line));
+    }
+}
@@ -1,13 +1,39 @@
+//! GCode parser using Nom.
+//!
+//! The crate is `no_std` compatible (it only requires `alloc`) when built with
+//! `default-features = false`. Without `std` the parsers, [MachineState], slicer metadata and
+//! thumbnails are available. The default `std` feature adds:
+//!
+//! - `std::error::Error` implementations for the error types and I/O errors
+//! - Toolpath analysis (eg. `toolpath`, `estimate_print_time` and `bounds`) and program
+//!   transformations (eg. `linearize_arcs` and `transform_gcode`), which need floating point
+//!   math from `std`
+//!
+//! There is no separate heapless mode: `alloc` is always required. Lines with up to
+//! [INLINE_ARGS_CAPACITY] arguments and comments are stored inline in their [GCode] though, so
+//! parsing them does not allocate.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 extern crate nom;
 
-use std::fmt;
-use thiserror::Error;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+};
+use core::fmt;
+use core::time::Duration;
+use smallvec::SmallVec;
 
 mod mnemonic;
 pub use mnemonic::*;
 
 mod parse_command;
-pub use parse_command::parse_command;
+pub use parse_command::{
+    parse_command,
+    parse_extended_command,
+};
 
 mod parse_args;
 pub use parse_args::parse_args;
@@ -19,14 +45,154 @@ pub use parse_comments::*;
 mod parse_gcode;
 pub use parse_gcode::parse_gcode;
 
-#[derive(Error, Debug)]
+mod parse_bytes;
+pub use parse_bytes::parse_gcode_bytes;
+
+mod parse_lines;
+pub use parse_lines::*;
+
+mod parse_streaming;
+pub use parse_streaming::{
+    parse_gcode_streaming,
+    StreamingError,
+};
+
+mod machine_state;
+pub use machine_state::*;
+
+mod metadata;
+pub use metadata::*;
+
+mod thumbnails;
+pub use thumbnails::*;
+
+// Toolpath analysis and transformations need floating point math from std
+#[cfg(feature = "std")]
+mod toolpath;
+#[cfg(feature = "std")]
+pub use toolpath::*;
+
+#[cfg(feature = "std")]
+mod rewrite;
+
+#[cfg(feature = "std")]
+mod arcs;
+#[cfg(feature = "std")]
+pub use arcs::*;
+
+#[cfg(feature = "std")]
+mod print_time;
+#[cfg(feature = "std")]
+pub use print_time::*;
+
+#[cfg(feature = "std")]
+mod filament;
+#[cfg(feature = "std")]
+pub use filament::*;
+
+#[cfg(feature = "std")]
+mod layers;
+#[cfg(feature = "std")]
+pub use layers::*;
+
+#[cfg(feature = "std")]
+mod layer_insertion;
+#[cfg(feature = "std")]
+pub use layer_insertion::*;
+
+#[cfg(feature = "std")]
+mod calibration;
+#[cfg(feature = "std")]
+pub use calibration::*;
+
+#[cfg(feature = "std")]
+mod resume;
+#[cfg(feature = "std")]
+pub use resume::*;
+
+#[cfg(feature = "std")]
+mod bounds;
+#[cfg(feature = "std")]
+pub use bounds::*;
+
+#[cfg(feature = "std")]
+mod features;
+#[cfg(feature = "std")]
+pub use features::*;
+
+#[cfg(feature = "std")]
+mod transform;
+#[cfg(feature = "std")]
+pub use transform::*;
+
+#[cfg(feature = "std")]
+mod unit_conversion;
+#[cfg(feature = "std")]
+pub use unit_conversion::*;
+
+#[cfg(feature = "std")]
+mod positioning;
+#[cfg(feature = "std")]
+pub use positioning::*;
+
+#[cfg(feature = "std")]
+mod objects;
+#[cfg(feature = "std")]
+pub use objects::*;
+
+#[cfg(feature = "tokio")]
+mod parse_async;
+#[cfg(feature = "tokio")]
+pub use parse_async::*;
+
+#[derive(Debug)]
 pub enum GCodeParseError {
-    #[error("Invalid GCode. GCodes must start with a letter, a number and a space. Got: {0}")]
     InvalidGCode(String),
-    #[error("Badly formatted GCode arguments. Got: {0}")]
     InvalidArguments(String),
-    #[error("Badly formatted GCode comment. Got: {0}")]
     InvalidComment(String),
+    /// The options of a transformation are invalid (eg. a tolerance that is not positive).
+    InvalidOptions(String),
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+// Display is implemented by hand so that it is available without std
+impl fmt::Display for GCodeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GCodeParseError::InvalidGCode(text) => write!(
+                f,
+                "Invalid GCode. GCodes must start with a letter, a number and a space. Got: {}",
+                text,
+            ),
+            GCodeParseError::InvalidArguments(text) => {
+                write!(f, "Badly formatted GCode arguments. Got: {}", text)
+            }
+            GCodeParseError::InvalidComment(text) => {
+                write!(f, "Badly formatted GCode comment. Got: {}", text)
+            }
+            GCodeParseError::InvalidOptions(options) => write!(f, "Invalid options. Got: {}", options),
+            #[cfg(feature = "std")]
+            GCodeParseError::Io(err) => write!(f, "Unable to read GCode: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for GCodeParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GCodeParseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for GCodeParseError {
+    fn from(err: std::io::Error) -> Self {
+        GCodeParseError::Io(err)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -34,14 +200,36 @@ pub struct Comment<'r>(
     pub &'r str
 );
 
+/// A comment that is not valid UTF-8 (eg. a Latin-1 comment written by an older CAM system).
+///
+/// Only produced by [parse_gcode_bytes].
+#[derive(Debug, PartialEq, Clone)]
+pub struct RawComment<'r>(
+    pub &'r [u8]
+);
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum DocComment<'r> {
     GCodeFlavor(&'r str),
-    PrintTime(std::time::Duration),
+    PrintTime(Duration),
     FilamentUsed { meters: f64 },
     LayerHeight { millis: f64 },
+    /// The start of a layer, numbered by the slicer (eg. `;LAYER:3`). Cura numbers raft layers
+    /// below zero.
+    Layer(i32),
+    /// The start of a layer without a number (eg. `;LAYER_CHANGE`).
+    LayerChange,
+    /// The Z height of the current layer (eg. `;Z:0.6`).
+    LayerZ { millis: f64 },
+    /// The thickness of the current layer (eg. `;HEIGHT:0.2`).
+    LayerThickness { millis: f64 },
+    /// The type of feature the following moves print (eg. `;TYPE:WALL-OUTER` or
+    /// `;TYPE:External perimeter`).
+    FeatureType(&'r str),
 }
 
+// GCodes are stored inline so that parsing a line does not allocate
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum GCodeLine<'r> {
     /// The first non-blank line of a file may contain nothing but a percent sign, %, possibly
@@ -51,7 +239,9 @@ pub enum GCodeLine<'r> {
     /// http://linuxcnc.org/docs/html/gcode/overview.html
     FileDemarcator,
     GCode(GCode<'r>),
+    ExtendedCommand(ExtendedCommand<'r>),
     Comment(Comment<'r>),
+    RawComment(RawComment<'r>),
     DocComment(DocComment<'r>),
 }
 
@@ -67,7 +257,49 @@ pub struct GCode<'r> {
     pub mnemonic: Mnemonic,
     pub major: u32,
     pub minor: u32,
-    args_or_comments: Option<Vec<ArgOrComment<'r>>>,
+    args_or_comments: Option<ArgsOrComments<'r>>,
+}
+
+/// A command named by a word rather than a letter and a number, eg. Klipper's
+/// `SET_PRESSURE_ADVANCE ADVANCE=0.04` or a `PRINT_START` macro.
+///
+/// Names are upper case and contain an underscore, other than Klipper's single word commands
+/// (eg. `PAUSE`). Firmwares define their own extended commands so their arguments are kept as
+/// text. They do not change the [MachineState] and are copied unchanged by transformations.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExtendedCommand<'r> {
+    pub name: &'r str,
+    /// The text between the name and the comment (if any), eg. "ADVANCE=0.04".
+    pub args: &'r str,
+    pub comment: Option<Comment<'r>>,
+}
+
+impl<'r> ExtendedCommand<'r> {
+    /// Returns the value of the first `KEY=VALUE` argument with the given key (case insensitive).
+    pub fn argument(&self, key: &str) -> Option<&'r str> {
+        self.args
+            .split_whitespace()
+            .find_map(|arg| {
+                let (k, value) = arg.split_once('=')?;
+
+                if k.eq_ignore_ascii_case(key) {
+                    Some(value)
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+/// Serializes an extended command as `<name> <arguments>`. Comments are not included.
+impl<'r> fmt::Display for ExtendedCommand<'r> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.args.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{} {}", self.name, self.args)
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -75,19 +307,43 @@ pub enum ArgOrComment<'r> {
     KeyValue(KeyValue),
     TextArg(&'r str),
     Comment(Comment<'r>),
+    RawComment(RawComment<'r>),
 }
 
-pub type KeyValue = (char, Option<f32>);
+/// A GCode argument's letter and value (eg. `('X', Some(10.5))` for `X10.5`).
+///
+/// Values are stored as `f64` so that large CNC coordinates and long decimal extrusion values
+/// survive parsing and re-serializing without rounding.
+pub type KeyValue = (char, Option<f64>);
 
+/// Number of arguments and comments stored inline in a [GCode] before spilling to the heap.
+///
+/// Lines with up to this many arguments and comments are parsed without any heap allocations.
+pub const INLINE_ARGS_CAPACITY: usize = 8;
+
+/// The arguments and comments of a GCode line.
+pub type ArgsOrComments<'r> = SmallVec<[ArgOrComment<'r>; INLINE_ARGS_CAPACITY]>;
+
+/// Serializes a GCode as `[N<line number>] <command> <arguments> [<text argument>]`, eg.
+/// "N10 G1 X10 E0.5". Comments are not included.
 impl<'r> fmt::Display for GCode<'r> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let gcode = format!("{}{}.{}", self.mnemonic, self.major, self.minor);
+        // Minor versions are omitted when zero since not all firmwares accept eg. "G1.0"
+        let gcode = if self.minor == 0 {
+            format!("{}{}", self.mnemonic, self.major)
+        } else {
+            format!("{}{}.{}", self.mnemonic, self.major, self.minor)
+        };
 
         let mut words = vec![gcode];
 
+        if let Some(line_number) = self.line_number {
+            words.insert(0, format!("N{}", line_number));
+        }
+
         let arg_words = self.arguments()
             .map(|(k, v)| {
-                format!("{}{}", k, v.map(|f| f.to_string()).unwrap_or("".to_string()))
+                format!("{}{}", k, v.map(|f| f.to_string()).unwrap_or_default())
             });
 
         words.extend(arg_words);
@@ -101,13 +357,61 @@ impl<'r> fmt::Display for GCode<'r> {
 }
 
 impl<'r> GCode<'r> {
-    // #[inline(always)]
-    fn args_or_comments_iter(&self) -> impl Iterator<Item = &ArgOrComment<'r>> {
-        use std::convert::identity;
+    /// Creates a GCode without any arguments (eg. `GCode::new(G, 1, 0)` for "G1").
+    pub fn new(mnemonic: Mnemonic, major: u32, minor: u32) -> Self {
+        Self {
+            line_number: None,
+            mnemonic,
+            major,
+            minor,
+            args_or_comments: None,
+        }
+    }
+
+    /// Sets an argument, replacing the value of the first argument with the same letter or
+    /// appending it if there is none.
+    pub fn set_argument(&mut self, key: char, value: Option<f64>) {
+        let args_or_comments = self.args_or_comments.get_or_insert_with(ArgsOrComments::new);
+
+        let existing_arg = args_or_comments
+            .iter_mut()
+            .find_map(|ac| match ac {
+                ArgOrComment::KeyValue(arg) if arg.0.eq_ignore_ascii_case(&key) => Some(arg),
+                _ => None,
+            });
+
+        match existing_arg {
+            Some(arg) => arg.1 = value,
+            None => args_or_comments.push(ArgOrComment::KeyValue((key, value))),
+        }
+    }
+
+    /// Builder-style [GCode::set_argument].
+    pub fn with_argument(mut self, key: char, value: Option<f64>) -> Self {
+        self.set_argument(key, value);
+        self
+    }
 
+    /// Removes every argument with the given letter (case insensitive), returning the first one.
+    pub fn remove_argument(&mut self, key: char) -> Option<KeyValue> {
+        let removed = self.argument(key).copied()?;
+
+        self.args_or_comments.as_mut()?.retain(|ac| !matches!(
+            ac,
+            ArgOrComment::KeyValue((k, _)) if k.eq_ignore_ascii_case(&key),
+        ));
+
+        Some(removed)
+    }
+
+    // #[inline(always)]
+    fn args_or_comments_iter(&self) -> core::slice::Iter<'_, ArgOrComment<'r>> {
+        // A concrete slice iterator (rather than an impl Iterator over the invariant SmallVec) lets
+        // the iterators returned below shorten 'r.
         self.args_or_comments
+            .as_deref()
+            .unwrap_or_default()
             .iter()
-            .flat_map(identity)
     }
 
     // #[inline(always)]
@@ -122,6 +426,18 @@ impl<'r> GCode<'r> {
             })
     }
 
+    /// Returns the first argument with the given letter (case insensitive).
+    pub fn argument(&self, key: char) -> Option<&KeyValue> {
+        self.arguments()
+            .find(|(k, _)| k.eq_ignore_ascii_case(&key))
+    }
+
+    /// Returns the value of the first argument with the given letter (case insensitive).
+    pub fn value(&self, key: char) -> Option<f64> {
+        self.argument(key)
+            .and_then(|(_, v)| *v)
+    }
+
     // #[inline(always)]
     pub fn arguments(&self) -> impl Iterator<Item = &KeyValue> {
         self.args_or_comments_iter()
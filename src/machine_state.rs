@@ -0,0 +1,355 @@
+use alloc::collections::BTreeMap;
+
+use super::{
+    GCode,
+    Mnemonic::*,
+};
+
+/// Millimetres per inch.
+pub const MM_PER_INCH: f64 = 25.4;
+
+/// The positional axes of a 3D printer or 3 axis CNC machine, in the order they are stored in
+/// positions (eg. [MachineState::position]).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+    /// The extruder.
+    E,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 4] = [Axis::X, Axis::Y, Axis::Z, Axis::E];
+
+    /// The argument letter of the axis (eg. 'X').
+    pub fn letter(self) -> char {
+        match self {
+            Axis::X => 'X',
+            Axis::Y => 'Y',
+            Axis::Z => 'Z',
+            Axis::E => 'E',
+        }
+    }
+
+    /// The index of the axis in a position.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// The units of lengths and feedrates (G20/G21).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Units {
+    Millimeters,
+    Inches,
+}
+
+impl Units {
+    pub fn to_millimeters(self, value: f64) -> f64 {
+        match self {
+            Units::Millimeters => value,
+            Units::Inches => value * MM_PER_INCH,
+        }
+    }
+
+    pub fn from_millimeters(self, millis: f64) -> f64 {
+        match self {
+            Units::Millimeters => millis,
+            Units::Inches => millis / MM_PER_INCH,
+        }
+    }
+}
+
+/// Absolute (G90/M82) or relative (G91/M83) positioning.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Positioning {
+    Absolute,
+    Relative,
+}
+
+/// The plane used for arcs (G17/G18/G19).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Plane {
+    XY,
+    ZX,
+    YZ,
+}
+
+impl Plane {
+    /// The plane's two axes followed by the axis normal to it, ordered so that arcs in the plane
+    /// are counter-clockwise from the first axis to the second (eg. Z, X, Y for G18).
+    pub fn axes(self) -> [Axis; 3] {
+        match self {
+            Plane::XY => [Axis::X, Axis::Y, Axis::Z],
+            Plane::ZX => [Axis::Z, Axis::X, Axis::Y],
+            Plane::YZ => [Axis::Y, Axis::Z, Axis::X],
+        }
+    }
+
+    /// The arc center offset letters of the plane's two axes (eg. 'I', 'J' for G17).
+    pub fn offset_letters(self) -> [char; 2] {
+        match self {
+            Plane::XY => ['I', 'J'],
+            Plane::ZX => ['K', 'I'],
+            Plane::YZ => ['J', 'K'],
+        }
+    }
+}
+
+/// The number of work coordinate systems (G54 to G59.3).
+pub const COORDINATE_SYSTEM_COUNT: usize = 9;
+
+/// The modal state of a machine, updated by applying each GCode of a program in order.
+///
+/// All lengths are stored in millimetres and feedrates in millimetres per minute regardless of
+/// the active [Units].
+#[derive(Debug, PartialEq, Clone)]
+pub struct MachineState {
+    /// The current position in the active work coordinate system (ie. the coordinates absolute
+    /// moves are relative to).
+    pub position: [f64; 4],
+    pub units: Units,
+    /// The positioning of the X, Y and Z axes (G90/G91). Also applies to the extruder while
+    /// relative.
+    pub positioning: Positioning,
+    /// The positioning of the extruder (M82/M83).
+    pub extruder_positioning: Positioning,
+    pub plane: Plane,
+    /// The feedrate of motion commands in millimetres per minute.
+    pub feedrate: Option<f64>,
+    /// Offsets set by G92.
+    pub position_offset: [f64; 4],
+    /// The index of the active work coordinate system, 0 being G54 and 8 being G59.3.
+    pub coordinate_system: usize,
+    /// The X, Y and Z offsets of each work coordinate system.
+    pub coordinate_system_offsets: [[f64; 3]; COORDINATE_SYSTEM_COUNT],
+    /// The active tool (T0, T1, ...).
+    pub tool: u32,
+    /// The target temperature of each hotend, indexed by tool.
+    pub hotend_temperatures: BTreeMap<u32, f64>,
+    pub bed_temperature: Option<f64>,
+    /// The speed of each part cooling fan from 0 to 255 (M106/M107), indexed by fan.
+    pub fan_speeds: BTreeMap<u32, f64>,
+}
+
+impl Default for MachineState {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 4],
+            units: Units::Millimeters,
+            positioning: Positioning::Absolute,
+            extruder_positioning: Positioning::Absolute,
+            plane: Plane::XY,
+            feedrate: None,
+            position_offset: [0.0; 4],
+            coordinate_system: 0,
+            coordinate_system_offsets: [[0.0; 3]; COORDINATE_SYSTEM_COUNT],
+            tool: 0,
+            hotend_temperatures: BTreeMap::new(),
+            bed_temperature: None,
+            fan_speeds: BTreeMap::new(),
+        }
+    }
+}
+
+impl MachineState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if moves along the axis are relative to the current position.
+    pub fn is_relative(&self, axis: Axis) -> bool {
+        self.positioning == Positioning::Relative
+            || (axis == Axis::E && self.extruder_positioning == Positioning::Relative)
+    }
+
+    /// The combined G92 and work coordinate system offset of an axis.
+    pub fn work_offset(&self, axis: Axis) -> f64 {
+        let coordinate_system_offset = self.coordinate_system_offsets[self.coordinate_system]
+            .get(axis.index())
+            .copied()
+            .unwrap_or(0.0);
+
+        self.position_offset[axis.index()] + coordinate_system_offset
+    }
+
+    /// The current position in machine coordinates (ie. without any work offsets).
+    pub fn machine_position(&self) -> [f64; 4] {
+        let mut position = self.position;
+
+        for axis in Axis::ALL.iter().copied() {
+            position[axis.index()] += self.work_offset(axis);
+        }
+
+        position
+    }
+
+    /// Converts an argument of the current units to millimetres.
+    pub fn to_millimeters(&self, value: f64) -> f64 {
+        self.units.to_millimeters(value)
+    }
+
+    /// The position a motion command (eg. G1) moves to, in the active work coordinate system.
+    pub fn target(&self, gcode: &GCode) -> [f64; 4] {
+        let mut target = self.position;
+
+        for axis in Axis::ALL.iter().copied() {
+            if let Some(value) = gcode.value(axis.letter()) {
+                let value = self.to_millimeters(value);
+
+                target[axis.index()] = if self.is_relative(axis) {
+                    self.position[axis.index()] + value
+                } else {
+                    value
+                };
+            }
+        }
+
+        target
+    }
+
+    /// Updates the state with a GCode. Unrecognized GCodes are ignored.
+    pub fn apply(&mut self, gcode: &GCode) {
+        match (gcode.mnemonic, gcode.major, gcode.minor) {
+            // Motion
+            (General, 0..=3, 0) => {
+                if let Some(feedrate) = gcode.value('F') {
+                    self.feedrate = Some(self.to_millimeters(feedrate));
+                }
+
+                self.position = self.target(gcode);
+            }
+            (General, 10, 0) => self.set_coordinate_system_offsets(gcode),
+            (General, 17, 0) => self.plane = Plane::XY,
+            (General, 18, 0) => self.plane = Plane::ZX,
+            (General, 19, 0) => self.plane = Plane::YZ,
+            (General, 20, 0) => self.units = Units::Inches,
+            (General, 21, 0) => self.units = Units::Millimeters,
+            (General, 28, 0) => self.home(gcode),
+            (General, 54..=58, 0) => {
+                self.select_coordinate_system(gcode.major as usize - 54);
+            }
+            (General, 59, 0..=3) => {
+                self.select_coordinate_system(5 + gcode.minor as usize);
+            }
+            (General, 90, 0) => self.positioning = Positioning::Absolute,
+            (General, 91, 0) => self.positioning = Positioning::Relative,
+            (General, 92, 0) => self.set_position(gcode),
+            (General, 92, 1) => {
+                self.preserving_machine_position(|state| state.position_offset = [0.0; 4]);
+            }
+            (Miscellaneous, 6, 0) => {
+                if let Some(tool) = gcode.value('T') {
+                    self.tool = tool as u32;
+                }
+            }
+            (Miscellaneous, 82, 0) => self.extruder_positioning = Positioning::Absolute,
+            (Miscellaneous, 83, 0) => self.extruder_positioning = Positioning::Relative,
+            (Miscellaneous, 104, 0) | (Miscellaneous, 109, 0) => {
+                let tool = gcode.value('T')
+                    .map(|tool| tool as u32)
+                    .unwrap_or(self.tool);
+
+                if let Some(temperature) = gcode.value('S').or_else(|| gcode.value('R')) {
+                    self.hotend_temperatures.insert(tool, temperature);
+                }
+            }
+            (Miscellaneous, 106, 0) => {
+                let fan = gcode.value('P').map(|fan| fan as u32).unwrap_or(0);
+                self.fan_speeds.insert(fan, gcode.value('S').unwrap_or(255.0));
+            }
+            (Miscellaneous, 107, 0) => {
+                let fan = gcode.value('P').map(|fan| fan as u32).unwrap_or(0);
+                self.fan_speeds.insert(fan, 0.0);
+            }
+            (Miscellaneous, 140, 0) | (Miscellaneous, 190, 0) => {
+                if let Some(temperature) = gcode.value('S').or_else(|| gcode.value('R')) {
+                    self.bed_temperature = Some(temperature);
+                }
+            }
+            (ToolChange, tool, 0) => self.tool = tool,
+            _ => {}
+        }
+    }
+
+    /// Runs a function that changes the work offsets, updating the position so that the machine
+    /// does not move.
+    fn preserving_machine_position<F: FnOnce(&mut Self)>(&mut self, f: F) {
+        let machine_position = self.machine_position();
+
+        f(self);
+
+        for axis in Axis::ALL.iter().copied() {
+            self.position[axis.index()] = machine_position[axis.index()] - self.work_offset(axis);
+        }
+    }
+
+    /// G28: Homes the given axes (or X, Y and Z if none are given) and resets their G92 offsets.
+    fn home(&mut self, gcode: &GCode) {
+        let axes = [Axis::X, Axis::Y, Axis::Z];
+        let home_all = !axes.iter().any(|axis| gcode.argument(axis.letter()).is_some());
+
+        for axis in axes.iter().copied() {
+            if home_all || gcode.argument(axis.letter()).is_some() {
+                self.position_offset[axis.index()] = 0.0;
+                self.position[axis.index()] = -self.work_offset(axis);
+            }
+        }
+    }
+
+    /// G92: Sets the current position of the given axes without moving.
+    fn set_position(&mut self, gcode: &GCode) {
+        let machine_position = self.machine_position();
+
+        for axis in Axis::ALL.iter().copied() {
+            if let Some(value) = gcode.value(axis.letter()) {
+                let i = axis.index();
+
+                self.position[i] = self.to_millimeters(value);
+                self.position_offset[i] = 0.0;
+                self.position_offset[i] = machine_position[i] - self.work_offset(axis) - self.position[i];
+            }
+        }
+    }
+
+    fn select_coordinate_system(&mut self, coordinate_system: usize) {
+        self.preserving_machine_position(|state| state.coordinate_system = coordinate_system);
+    }
+
+    /// G10 L2 / G10 L20: Sets the offsets of a work coordinate system (P1 to P9 for G54 to G59.3,
+    /// or P0 for the active one).
+    ///
+    /// G10 without an L argument (firmware retraction) does not change the state.
+    fn set_coordinate_system_offsets(&mut self, gcode: &GCode) {
+        let l = gcode.value('L').map(|l| l as u32);
+
+        if l != Some(2) && l != Some(20) {
+            return;
+        }
+
+        let coordinate_system = match gcode.value('P').map(|p| p as usize) {
+            None | Some(0) => self.coordinate_system,
+            Some(p) if p <= COORDINATE_SYSTEM_COUNT => p - 1,
+            Some(_) => return,
+        };
+
+        let machine_position = self.machine_position();
+
+        self.preserving_machine_position(|state| {
+            for axis in [Axis::X, Axis::Y, Axis::Z].iter().copied() {
+                if let Some(value) = gcode.value(axis.letter()) {
+                    let i = axis.index();
+                    let value = state.to_millimeters(value);
+
+                    state.coordinate_system_offsets[coordinate_system][i] = if l == Some(2) {
+                        value
+                    } else {
+                        // L20: Offset the coordinate system so that the current position is at value
+                        machine_position[i] - state.position_offset[i] - value
+                    };
+                }
+            }
+        });
+    }
+}
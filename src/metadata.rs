@@ -0,0 +1,320 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::time::Duration;
+
+/// The slicers [slicer_metadata] recognizes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Slicer {
+    Cura,
+    PrusaSlicer,
+    SuperSlicer,
+    OrcaSlicer,
+    BambuStudio,
+    Simplify3D,
+}
+
+/// Information about how a file was sliced, read from the comments slicers write. See
+/// [slicer_metadata].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SlicerMetadata {
+    pub slicer: Option<Slicer>,
+    /// The slicer's version (eg. "2.6.0+linux-x64").
+    pub version: Option<String>,
+    /// The nozzle diameter of each extruder in millimetres.
+    pub nozzle_diameters: Vec<f64>,
+    /// The hotend temperature of each extruder.
+    pub hotend_temperatures: Vec<f64>,
+    pub bed_temperature: Option<f64>,
+    /// The slicer's estimate of the print time.
+    pub print_time: Option<Duration>,
+    /// The length of filament used by each extruder in millimetres.
+    pub filament_used: Vec<f64>,
+    /// Every setting and header value the slicer wrote, by the slicer's own names (eg.
+    /// "nozzle_diameter" for PrusaSlicer, "extruderDiameter" for Simplify3D).
+    pub settings: BTreeMap<String, String>,
+}
+
+/// Extracts slicer metadata from the comments of a GCode file.
+///
+/// Supports Cura's `;KEY:value` header and `;SETTING_3` profile, PrusaSlicer's (and
+/// SuperSlicer's) `; key = value` config block, OrcaSlicer and Bambu Studio's header and config
+/// blocks, and Simplify3D's `;   setting,value` lines and build summary.
+///
+/// Lines that are not comments are ignored, so files with invalid GCode can still be inspected.
+pub fn slicer_metadata(input: &str) -> SlicerMetadata {
+    let mut metadata = SlicerMetadata::default();
+    let mut cura_settings = String::new();
+    let mut in_header = true;
+
+    for line in input.lines() {
+        let line = line.trim();
+
+        let body = match line.strip_prefix(';') {
+            Some(body) => body,
+            None => {
+                if !line.is_empty() {
+                    in_header = false;
+                }
+                continue;
+            }
+        };
+
+        if metadata.slicer.is_none() {
+            if let Some((slicer, version)) = detect_slicer(body) {
+                metadata.slicer = Some(slicer);
+                metadata.version = version.map(ToString::to_string);
+                continue;
+            }
+        }
+
+        if let Some(json) = body.strip_prefix("SETTING_3 ") {
+            cura_settings.push_str(json);
+        } else if let Some((key, value)) = body.split_once(" = ") {
+            insert_setting(&mut metadata.settings, key, value);
+        } else if metadata.slicer == Some(Slicer::Simplify3D) && body.starts_with("   ") {
+            // Settings are comma separated and the build summary is colon separated
+            if let Some((key, value)) = body.split_once([',', ':']) {
+                insert_setting(&mut metadata.settings, key, value);
+            }
+        } else if in_header {
+            // Orca writes several values per line (eg. "; model printing time: 5m; total
+            // estimated time: 6m")
+            for pair in body.split("; ") {
+                if let Some((key, value)) = pair.split_once(':') {
+                    insert_setting(&mut metadata.settings, key, value);
+                }
+            }
+        }
+    }
+
+    insert_cura_settings(&mut metadata.settings, &cura_settings);
+
+    let settings = &metadata.settings;
+
+    metadata.print_time = match metadata.slicer {
+        Some(Slicer::Cura) => setting(settings, "TIME")
+            .and_then(|time| time.parse().ok())
+            .map(Duration::from_secs),
+        _ => first_setting(settings, &[
+            "estimated printing time (normal mode)",
+            "total estimated time",
+            "Build time",
+        ])
+            .and_then(parse_duration),
+    };
+
+    metadata.filament_used = match metadata.slicer {
+        Some(Slicer::Cura) => setting(settings, "Filament used")
+            .map(|meters| {
+                number_list(&meters.replace('m', ""))
+                    .into_iter()
+                    .map(|meters| meters * 1000.0)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        // eg. "3541.9 mm (3.54 m)"
+        Some(Slicer::Simplify3D) => setting(settings, "Filament length")
+            .and_then(|length| length.split_whitespace().next()?.parse().ok())
+            .into_iter()
+            .collect(),
+        _ => first_setting(settings, &["filament used [mm]", "total filament length [mm]"])
+            .map(number_list)
+            .unwrap_or_default(),
+    };
+
+    match metadata.slicer {
+        Some(Slicer::Cura) => {
+            metadata.nozzle_diameters = extruder_train_settings(settings, "NOZZLE.DIAMETER");
+            metadata.hotend_temperatures = extruder_train_settings(settings, "INITIAL_TEMPERATURE");
+            metadata.bed_temperature = first_setting(settings, &[
+                "BUILD_PLATE.INITIAL_TEMPERATURE",
+                "material_bed_temperature",
+            ])
+                .and_then(|temperature| temperature.parse().ok());
+        }
+        Some(Slicer::Simplify3D) => {
+            metadata.nozzle_diameters = setting(settings, "extruderDiameter")
+                .map(number_list)
+                .unwrap_or_default();
+
+            // Temperature controllers are listed together with flags marking the heated bed
+            let temperatures = setting(settings, "temperatureSetpointTemperatures")
+                .map(number_list)
+                .unwrap_or_default();
+            let heated_bed_flags = setting(settings, "temperatureHeatedBed")
+                .map(number_list)
+                .unwrap_or_default();
+
+            for (i, temperature) in temperatures.into_iter().enumerate() {
+                if heated_bed_flags.get(i) == Some(&1.0) {
+                    metadata.bed_temperature.get_or_insert(temperature);
+                } else {
+                    metadata.hotend_temperatures.push(temperature);
+                }
+            }
+        }
+        _ => {
+            metadata.nozzle_diameters = setting(settings, "nozzle_diameter")
+                .map(number_list)
+                .unwrap_or_default();
+            metadata.hotend_temperatures = first_setting(settings, &["temperature", "nozzle_temperature"])
+                .map(number_list)
+                .unwrap_or_default();
+            metadata.bed_temperature = first_setting(settings, &["bed_temperature", "hot_plate_temp"])
+                .and_then(|temperatures| number_list(temperatures).first().copied());
+        }
+    }
+
+    metadata
+}
+
+/// Detects the slicer and its version from a comment (without the leading semicolon).
+fn detect_slicer(body: &str) -> Option<(Slicer, Option<&str>)> {
+    let slicers = [
+        ("Generated with Cura_SteamEngine", Slicer::Cura),
+        ("generated by PrusaSlicer", Slicer::PrusaSlicer),
+        ("generated by SuperSlicer", Slicer::SuperSlicer),
+        ("generated by OrcaSlicer", Slicer::OrcaSlicer),
+        ("BambuStudio", Slicer::BambuStudio),
+        ("G-Code generated by Simplify3D(R) Version", Slicer::Simplify3D),
+    ];
+
+    let body = body.trim();
+
+    slicers.iter().find_map(|(prefix, slicer)| {
+        let version = body.strip_prefix(prefix)?.split_whitespace().next();
+
+        Some((*slicer, version))
+    })
+}
+
+fn insert_setting(settings: &mut BTreeMap<String, String>, key: &str, value: &str) {
+    let key = key.trim();
+
+    if !key.is_empty() {
+        settings.insert(key.to_string(), value.trim().to_string());
+    }
+}
+
+fn setting<'a>(settings: &'a BTreeMap<String, String>, key: &str) -> Option<&'a str> {
+    settings.get(key).map(String::as_str)
+}
+
+fn first_setting<'a>(settings: &'a BTreeMap<String, String>, keys: &[&str]) -> Option<&'a str> {
+    keys.iter().find_map(|key| setting(settings, key))
+}
+
+/// Parses a comma separated list of numbers (eg. "0.4,0.6"), skipping anything that is not a
+/// number.
+fn number_list(list: &str) -> Vec<f64> {
+    list.split(',')
+        .filter_map(|value| value.trim().parse().ok())
+        .collect()
+}
+
+/// Collects Cura's per-extruder header values (eg. `;EXTRUDER_TRAIN.1.NOZZLE.DIAMETER:0.4`) in
+/// extruder order.
+fn extruder_train_settings(settings: &BTreeMap<String, String>, name: &str) -> Vec<f64> {
+    (0..)
+        .map_while(|extruder| {
+            let key = alloc::format!("EXTRUDER_TRAIN.{}.{}", extruder, name);
+            setting(settings, &key)?.parse().ok()
+        })
+        .collect()
+}
+
+/// Parses durations such as "1d 2h 3m 4s" (PrusaSlicer and Orca) or "1 hours 2 minutes"
+/// (Simplify3D).
+fn parse_duration(text: &str) -> Option<Duration> {
+    let mut seconds = 0;
+    let mut found = false;
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().ok()?;
+
+        rest = rest[digits..].trim_start();
+
+        let unit_length = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        let multiplier = match rest[..unit_length].chars().next()? {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+
+        seconds += value * multiplier;
+        found = true;
+        rest = rest[unit_length..].trim_start();
+    }
+
+    if found {
+        Some(Duration::from_secs(seconds))
+    } else {
+        None
+    }
+}
+
+/// Adds the `[values]` of the profiles in Cura's `;SETTING_3` JSON blob. Extruder values are
+/// added after, and so override, the global values.
+fn insert_cura_settings(settings: &mut BTreeMap<String, String>, json: &str) {
+    for profile in json_strings(json) {
+        let mut in_values = false;
+
+        // Cura escapes the profiles' newlines before encoding them as JSON
+        for line in profile.replace("\\n", "\n").lines() {
+            let line = line.trim();
+
+            if line.starts_with('[') {
+                in_values = line == "[values]";
+            } else if in_values {
+                if let Some((key, value)) = line.split_once(" = ") {
+                    insert_setting(settings, key, value);
+                }
+            }
+        }
+    }
+}
+
+/// The unescaped string literals of a JSON document, in order.
+fn json_strings(json: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = json.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+
+        let mut string = String::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('u') => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        let c = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32);
+
+                        string.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    Some(c) => string.push(c),
+                    None => break,
+                },
+                c => string.push(c),
+            }
+        }
+
+        strings.push(string);
+    }
+
+    strings
+}
@@ -0,0 +1,377 @@
+use alloc::borrow::Cow;
+use core::ops::Range;
+
+use super::{
+    parse_lines,
+    rewrite::{rewrite_lines, round_value},
+    Axis,
+    Comment,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    MachineState,
+    ParsedLine,
+    Mnemonic::*,
+    Positioning,
+};
+
+/// An object of a print, eg. one of several parts on the bed. See [print_objects].
+#[derive(Debug, PartialEq, Clone)]
+pub struct PrintObject {
+    /// The index of the object in the order objects are first printed.
+    pub id: usize,
+    /// The slicer's name for the object (eg. "cube.stl id:0 copy 0"). Objects labelled with
+    /// `M486 S<n>` are named by their number.
+    pub name: String,
+    /// The 1-based line numbers of each section of the file printing the object, from its start
+    /// marker to its end marker.
+    pub sections: Vec<Range<usize>>,
+}
+
+/// A comment or command marking the start or end of an object.
+#[derive(Debug, PartialEq)]
+enum ObjectMarker<'r> {
+    Start(&'r str),
+    End,
+    /// A marker from the firmware labels added by [label_objects] (or a slicer) rather than from
+    /// a slicer comment.
+    Label(Option<Cow<'r, str>>),
+}
+
+/// Recognizes PrusaSlicer (`; printing object`), Cura (`;MESH:`), Marlin (`M486 S`) and
+/// Klipper (`EXCLUDE_OBJECT_START`) object markers.
+fn object_marker<'r>(gcode_line: &Option<GCodeLine<'r>>) -> Option<ObjectMarker<'r>> {
+    match gcode_line.as_ref()? {
+        GCodeLine::Comment(Comment(body)) => {
+            let body = body.trim();
+
+            if let Some(name) = body.strip_prefix("printing object ") {
+                Some(ObjectMarker::Start(name.trim()))
+            } else if body.starts_with("stop printing object") {
+                Some(ObjectMarker::End)
+            } else {
+                match body.strip_prefix("MESH:")?.trim() {
+                    "NONMESH" => Some(ObjectMarker::End),
+                    name => Some(ObjectMarker::Start(name)),
+                }
+            }
+        }
+        GCodeLine::ExtendedCommand(command) => {
+            if command.name.eq_ignore_ascii_case("EXCLUDE_OBJECT_START") {
+                let name = command.argument("NAME").unwrap_or_default();
+
+                Some(ObjectMarker::Label(Some(Cow::Borrowed(name))))
+            } else if command.name.eq_ignore_ascii_case("EXCLUDE_OBJECT_END") {
+                Some(ObjectMarker::Label(None))
+            } else {
+                None
+            }
+        }
+        GCodeLine::GCode(gcode) if gcode.mnemonic == Miscellaneous && gcode.major == 486 => {
+            // Only M486 S selects an object (eg. M486 T sets the object count)
+            let id = gcode.value('S')?;
+
+            if id < 0.0 {
+                Some(ObjectMarker::Label(None))
+            } else {
+                // M486 names objects by number
+                Some(ObjectMarker::Label(Some(Cow::Owned(id.to_string()))))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether the input has firmware object labels (eg. added by [label_objects]).
+fn has_labels(input: &str) -> Result<bool, GCodeParseError> {
+    for line in parse_lines(input) {
+        if let Some(ObjectMarker::Label(_)) = object_marker(&line?.gcode_line) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// The start (with the object's name) or end of an object's section. Firmware labels are used
+/// when the file has any, as slicers that write labels also write comments naming the objects
+/// differently.
+fn section_marker<'r>(
+    gcode_line: &Option<GCodeLine<'r>>,
+    labelled: bool,
+) -> Option<Option<Cow<'r, str>>> {
+    match (object_marker(gcode_line)?, labelled) {
+        (ObjectMarker::Start(name), false) => Some(Some(Cow::Borrowed(name))),
+        (ObjectMarker::End, false) => Some(None),
+        (ObjectMarker::Label(name), true) => Some(name),
+        _ => None,
+    }
+}
+
+/// Whether a line is the last line of the input.
+fn is_last_line(input: &str, line: &ParsedLine) -> bool {
+    matches!(&input[line.span.end..], "" | "\n" | "\r\n")
+}
+
+/// Finds the objects of a print from its firmware labels (`M486 S`, `EXCLUDE_OBJECT_START`) or,
+/// if it has none, the slicer's comments.
+///
+/// An object's section ends at its end marker or at the start of another object.
+pub fn print_objects(input: &str) -> Result<Vec<PrintObject>, GCodeParseError> {
+    let mut objects: Vec<PrintObject> = vec![];
+    // The object being printed and the line its section starts at
+    let mut current: Option<(usize, usize)> = None;
+    let mut line_count = 0;
+
+    let labelled = has_labels(input)?;
+
+    for line in parse_lines(input) {
+        let line = line?;
+        line_count = line.number;
+
+        let name = match section_marker(&line.gcode_line, labelled) {
+            Some(Some(name)) => name,
+            Some(None) => {
+                end_section(&mut objects, &mut current, line.number + 1);
+                continue;
+            }
+            None => continue,
+        };
+
+        end_section(&mut objects, &mut current, line.number);
+
+        let id = match objects.iter().position(|object| object.name == name) {
+            Some(id) => id,
+            None => {
+                objects.push(PrintObject {
+                    id: objects.len(),
+                    name: name.into_owned(),
+                    sections: vec![],
+                });
+
+                objects.len() - 1
+            }
+        };
+
+        current = Some((id, line.number));
+    }
+
+    end_section(&mut objects, &mut current, line_count + 1);
+
+    Ok(objects)
+}
+
+/// Ends the section of the object being printed (if any) before line `end`.
+fn end_section(objects: &mut [PrintObject], current: &mut Option<(usize, usize)>, end: usize) {
+    if let Some((id, start)) = current.take() {
+        objects[id].sections.push(start..end);
+    }
+}
+
+/// The firmware commands written by [label_objects].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ObjectLabels {
+    /// `M486 T<count>`, `M486 S<id>` and `M486 S-1`, as supported by Marlin, Prusa and
+    /// RepRapFirmware.
+    Marlin,
+    /// `EXCLUDE_OBJECT_DEFINE`, `EXCLUDE_OBJECT_START` and `EXCLUDE_OBJECT_END`.
+    Klipper,
+}
+
+/// Klipper object names cannot contain spaces.
+fn klipper_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect()
+}
+
+/// Labels the objects marked by PrusaSlicer (`; printing object`) or Cura (`;MESH:`) comments
+/// so that the firmware can cancel them mid-print. The objects are declared at the start of the
+/// file and each section of an object is wrapped in start and end labels.
+///
+/// Files that already have firmware labels are returned unchanged.
+pub fn label_objects(input: &str, labels: ObjectLabels) -> Result<String, GCodeParseError> {
+    let objects = print_objects(input)?;
+
+    if has_labels(input)? || objects.is_empty() {
+        return Ok(input.to_string());
+    }
+
+    let declarations = match labels {
+        ObjectLabels::Marlin => vec![format!("M486 T{}", objects.len())],
+        ObjectLabels::Klipper => objects
+            .iter()
+            .map(|object| format!("EXCLUDE_OBJECT_DEFINE NAME={}", klipper_name(&object.name)))
+            .collect(),
+    };
+
+    let start_label = |object: &PrintObject| match labels {
+        ObjectLabels::Marlin => format!("M486 S{}", object.id),
+        ObjectLabels::Klipper => {
+            format!("EXCLUDE_OBJECT_START NAME={}", klipper_name(&object.name))
+        }
+    };
+
+    let end_label = |object: &PrintObject| match labels {
+        ObjectLabels::Marlin => "M486 S-1".to_string(),
+        ObjectLabels::Klipper => {
+            format!("EXCLUDE_OBJECT_END NAME={}", klipper_name(&object.name))
+        }
+    };
+
+    let mut current: Option<&PrintObject> = None;
+
+    rewrite_lines(input, |line, _| {
+        let mut lines = if line.number == 1 { declarations.clone() } else { vec![] };
+
+        match object_marker(&line.gcode_line) {
+            Some(ObjectMarker::Start(name)) => {
+                lines.extend(current.take().map(end_label));
+                lines.push(line.text.to_string());
+
+                current = objects.iter().find(|object| object.name == name);
+                lines.extend(current.map(start_label));
+            }
+            Some(ObjectMarker::End) => {
+                lines.extend(current.take().map(end_label));
+                lines.push(line.text.to_string());
+            }
+            _ => lines.push(line.text.to_string()),
+        }
+
+        if is_last_line(input, line) {
+            lines.extend(current.take().map(end_label));
+        }
+
+        Some(lines.join("\n"))
+    })
+}
+
+/// The commands that return the machine to where the original program would be after moves
+/// were removed: a travel move to the position, an extruder position reset, the net extrusion of
+/// the removed retractions and primes (in millimetres) and the feedrate.
+fn resync(state: &MachineState, retraction: f64) -> Vec<GCode<'static>> {
+    let length = |millis: f64| Some(round_value(state.units.from_millimeters(millis)));
+    let [x, y, z, e] = state.position;
+
+    let mut commands = vec![];
+    let relative = state.positioning == Positioning::Relative;
+
+    if relative {
+        commands.push(GCode::new(General, 90, 0));
+    }
+
+    commands.push(
+        GCode::new(General, 0, 0)
+            .with_argument('X', length(x))
+            .with_argument('Y', length(y))
+            .with_argument('Z', length(z)),
+    );
+
+    if relative {
+        commands.push(GCode::new(General, 91, 0));
+    }
+
+    // Relative extrusion does not depend on the extruder position
+    if state.is_relative(Axis::E) {
+        if retraction != 0.0 {
+            commands.push(GCode::new(General, 1, 0).with_argument('E', length(retraction)));
+        }
+    } else if retraction != 0.0 {
+        commands.push(GCode::new(General, 92, 0).with_argument('E', length(e - retraction)));
+        commands.push(GCode::new(General, 1, 0).with_argument('E', length(e)));
+    } else {
+        commands.push(GCode::new(General, 92, 0).with_argument('E', length(e)));
+    }
+
+    if let Some(feedrate) = state.feedrate {
+        commands.push(GCode::new(General, 1, 0).with_argument('F', length(feedrate)));
+    }
+
+    commands
+}
+
+/// Removes objects (by [PrintObject::id]) from a print, eg. to skip a failed part when
+/// reprinting a plate.
+///
+/// The moves of the removed objects are dropped but every other command (eg. temperature, fan
+/// and G92 commands) is kept. After each removed section a travel move returns the nozzle to
+/// where the original program would be and the extruder position is resynchronized with G92 E,
+/// so the rest of the program (including layer changes inside removed sections) is unaffected.
+/// Retractions and primes (moves of only the extruder) inside removed sections are replaced by a
+/// single extruder move of their net length, so the filament is left as the original program
+/// would leave it.
+///
+/// Extended commands (eg. Klipper macros) are copied unchanged.
+pub fn remove_objects(input: &str, ids: &[usize]) -> Result<String, GCodeParseError> {
+    let objects = print_objects(input)?;
+
+    let is_removed = |name: &str| {
+        objects
+            .iter()
+            .any(|object| object.name == name && ids.contains(&object.id))
+    };
+
+    let labelled = has_labels(input)?;
+
+    let mut removing = false;
+    let mut removed_moves = false;
+    // The net extrusion of the retractions and primes removed since the last resync
+    let mut retraction = 0.0;
+
+    rewrite_lines(input, |line, state| {
+        let marker = section_marker(&line.gcode_line, labelled);
+        let mut lines = vec![];
+
+        let leaving_section = match &marker {
+            Some(Some(name)) => !is_removed(name),
+            Some(None) => true,
+            None => false,
+        };
+
+        if leaving_section {
+            if removed_moves {
+                lines.extend(resync(state, retraction).iter().map(GCode::to_string));
+            }
+
+            removing = false;
+            removed_moves = false;
+            retraction = 0.0;
+        }
+
+        if let Some(Some(name)) = &marker {
+            removing = is_removed(name);
+        }
+
+        let from = state.position;
+        let mut state = state.clone();
+
+        match &line.gcode_line {
+            Some(GCodeLine::GCode(gcode)) => {
+                state.apply(gcode);
+
+                let is_move = gcode.mnemonic == General && gcode.minor == 0 && gcode.major <= 3;
+
+                if removing && is_move {
+                    let to = state.position;
+
+                    if from[..3] == to[..3] {
+                        retraction += to[Axis::E.index()] - from[Axis::E.index()];
+                    }
+
+                    removed_moves = true;
+                } else {
+                    lines.push(line.text.to_string());
+                }
+            }
+            _ => lines.push(line.text.to_string()),
+        }
+
+        // Files ending in a removed section are resynchronized after their last line
+        if removed_moves && is_last_line(input, line) {
+            lines.extend(resync(&state, retraction).iter().map(GCode::to_string));
+        }
+
+        Some(lines.join("\n"))
+    })
+}
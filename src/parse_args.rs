@@ -13,10 +13,11 @@ use crate::{seimcolon_comment, Comment, comment};
 
 use super::{
     ArgOrComment,
+    ArgsOrComments,
 };
 
 type ArgOrCommentResult<'r> = IResult<&'r str, ArgOrComment<'r>>;
-pub type ManyArgOrCommentsResult<'r> = IResult<&'r str, Option<Vec<ArgOrComment<'r>>>>;
+pub type ManyArgOrCommentsResult<'r> = IResult<&'r str, Option<ArgsOrComments<'r>>>;
 
 /*
  * Conditionally parses a string arg if enabled is true.
@@ -51,8 +52,8 @@ fn key_value_arg<'r>(input: &'r str) -> ArgOrCommentResult<'r> {
 }
 
 fn combine_args_and_comments<'r>(
-    parser_outputs: (Vec<ArgOrComment<'r>>, Option<&'r str>),
-) -> Option<Vec<ArgOrComment<'r>>> {
+    parser_outputs: (ArgsOrComments<'r>, Option<&'r str>),
+) -> Option<ArgsOrComments<'r>> {
     let (mut args_or_comments, final_comment) = parser_outputs;
 
     if let Some(final_comment) = final_comment {
@@ -77,27 +78,39 @@ pub fn parse_args<'r>(
         // Add a Text argument for the string arg of certain MCodes (eg. M28 teg.gcode)
         map(
             tuple((
-                    opt(map(comment, |c| ArgOrComment::Comment(c))),
+                    opt(map(comment, ArgOrComment::Comment)),
                     opt(string_arg),
-                    opt(map(comment, |c| ArgOrComment::Comment(c))),
+                    opt(map(comment, ArgOrComment::Comment)),
                 ),
             ),
-            |(c1, arg, c2)| Some(vec![c1, arg, c2].into_iter().flatten().collect()),
+            |(c1, arg, c2)| Some(IntoIterator::into_iter([c1, arg, c2]).flatten().collect()),
         )(input)
     } else {
         map(
             tuple((
-                many0(
+                // Folding into a SmallVec (rather than many0's Vec) avoids heap allocations
+                fold_many0(
                     alt((
                         // Add the rest of the args and comments
                         preceded(
                             space1,
                             key_value_arg,
                         ),
-                        map(comment, |c| ArgOrComment::Comment(c)),
+                        preceded(
+                            space0,
+                            map(comment, ArgOrComment::Comment),
+                        ),
                     )),
+                    ArgsOrComments::new,
+                    |mut args_or_comments, arg_or_comment| {
+                        args_or_comments.push(arg_or_comment);
+                        args_or_comments
+                    },
+                ),
+                terminated(
+                    opt(seimcolon_comment),
+                    space0,
                 ),
-                opt(seimcolon_comment),
             )),
             combine_args_and_comments,
         )(input)
@@ -113,7 +126,7 @@ pub fn parse_kv_arg<'r>(
     //     // Add a Text argument for the string arg of certain MCodes (eg. M28 teg.gcode)
     //     alt((
     //         string_arg,
-    //         map(comment, |c| ArgOrComment::Comment(c)),
+    //         map(comment, ArgOrComment::Comment),
     //     ))(input)
     // } else {
     alt((
@@ -0,0 +1,28 @@
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio_stream::{wrappers::LinesStream, Stream, StreamExt};
+
+use super::{
+    GCodeParseError,
+    OwnedGCodeLine,
+};
+
+/// Parses the lines of a GCode file as they are read from an async reader.
+///
+/// Lines are only read as the stream is polled so a slow consumer applies backpressure to the
+/// reader, and partially received lines are buffered until their line ending (or the end of the
+/// file) arrives. Invalid lines produce an error but do not end the stream.
+pub fn gcode_line_stream<R>(
+    reader: R,
+) -> impl Stream<Item = Result<OwnedGCodeLine, GCodeParseError>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut number = 0;
+
+    LinesStream::new(reader.lines())
+        .map(move |text| {
+            number += 1;
+
+            OwnedGCodeLine::new(number, text?)
+        })
+}
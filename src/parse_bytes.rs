@@ -0,0 +1,167 @@
+use alloc::string::String;
+use core::{
+    ops::Range,
+    str,
+};
+
+use super::{
+    parse_args,
+    parse_gcode,
+    parse_gcode::BYTE_ORDER_MARK,
+    ArgsOrComments,
+    ArgOrComment,
+    Comment,
+    GCodeLine,
+    GCodeParseError,
+    GCodeParseError::*,
+    RawComment,
+};
+
+/// Finds the next comment at or after `from`, skipping escaped characters. Returns the byte
+/// range of the comment (including its delimiters) and of its body.
+fn next_comment(line: &[u8], from: usize) -> Option<(Range<usize>, Range<usize>)> {
+    let mut escaped = false;
+
+    for (i, byte) in line.iter().enumerate().skip(from) {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b';' => return Some((i..line.len(), i + 1..line.len())),
+            b'(' => {
+                let end = line[i..].iter().position(|byte| *byte == b')')? + i;
+
+                return Some((i..end + 1, i + 1..end));
+            }
+            _ => {},
+        }
+    }
+
+    None
+}
+
+fn lossy(input: &[u8]) -> String {
+    String::from_utf8_lossy(input).into()
+}
+
+/// Parses a single line of GCode from bytes.
+///
+/// GCode commands and arguments must be ASCII but comments (both `;` and `( … )` comments) may
+/// contain arbitrary bytes (eg. Latin-1 text), in which case they are returned as a [RawComment].
+/// A UTF-8 byte order mark at the start of the input is skipped.
+///
+/// Valid UTF-8 lines are parsed exactly as [parse_gcode] would parse them.
+// #[inline(always)]
+pub fn parse_gcode_bytes(input: &[u8]) -> Result<(&[u8], Option<GCodeLine<'_>>), GCodeParseError> {
+    let mut bom = [0; 3];
+    let input = input
+        .strip_prefix(BYTE_ORDER_MARK.encode_utf8(&mut bom).as_bytes())
+        .unwrap_or(input);
+
+    // Include the line ending so that empty lines consume it, the same as parse_gcode
+    let line_len = input
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map(|i| i + 1)
+        .unwrap_or(input.len());
+
+    let line = &input[..line_len];
+
+    if let Ok(line) = str::from_utf8(line) {
+        let (remainder, gcode_line) = parse_gcode(line)?;
+        let consumed = line.len() - remainder.len();
+
+        return Ok((&input[consumed..], gcode_line));
+    }
+
+    /*
+     * Non UTF-8 lines are only accepted if the invalid bytes are inside comments. The code between
+     * the comments is parsed piece by piece.
+     */
+
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    let mut gcode_line = None;
+    let mut position = 0;
+
+    loop {
+        let comment = next_comment(line, position);
+        let code_end = comment.as_ref().map(|(span, _)| span.start).unwrap_or(line.len());
+
+        let code = str::from_utf8(&line[position..code_end])
+            .map_err(|_| InvalidGCode(lossy(line)))?;
+
+        gcode_line = parse_code(gcode_line, code, line)?;
+
+        let (span, body) = match comment {
+            Some(comment) => comment,
+            None => break,
+        };
+
+        let body = &line[body];
+        let text = str::from_utf8(body).ok();
+
+        match &mut gcode_line {
+            Some(GCodeLine::GCode(gcode)) => {
+                let comment = text
+                    .map(|text| ArgOrComment::Comment(Comment(text)))
+                    .unwrap_or(ArgOrComment::RawComment(RawComment(body)));
+
+                gcode.args_or_comments
+                    .get_or_insert_with(ArgsOrComments::new)
+                    .push(comment);
+            }
+            // Comments after a line's first comment are dropped, as parse_gcode does
+            Some(_) => {},
+            None => {
+                gcode_line = Some(text
+                    .map(|text| GCodeLine::Comment(Comment(text)))
+                    .unwrap_or(GCodeLine::RawComment(RawComment(body))));
+            }
+        }
+
+        position = span.end;
+    }
+
+    Ok((&input[line.len()..], gcode_line))
+}
+
+/// Parses the code between the comments of a non UTF-8 line: the command and the arguments
+/// before the first comment, or more arguments of the command.
+fn parse_code<'r>(
+    gcode_line: Option<GCodeLine<'r>>,
+    code: &'r str,
+    line: &[u8],
+) -> Result<Option<GCodeLine<'r>>, GCodeParseError> {
+    if code.trim().is_empty() {
+        return Ok(gcode_line);
+    }
+
+    match gcode_line {
+        None => {
+            let (remainder, gcode_line) = parse_gcode(code)?;
+
+            if !remainder.trim().is_empty() {
+                return Err(InvalidArguments(lossy(line)));
+            }
+
+            Ok(gcode_line)
+        }
+        Some(GCodeLine::GCode(mut gcode)) => {
+            let (remainder, args_or_comments) = parse_args(false, code)
+                .map_err(|_| InvalidArguments(lossy(line)))?;
+
+            if !remainder.is_empty() {
+                return Err(InvalidArguments(lossy(line)));
+            }
+
+            gcode.args_or_comments
+                .get_or_insert_with(ArgsOrComments::new)
+                .extend(args_or_comments.into_iter().flatten());
+
+            Ok(Some(GCodeLine::GCode(gcode)))
+        }
+        // Commands cannot follow a comment line
+        Some(_) => Err(InvalidGCode(lossy(line))),
+    }
+}
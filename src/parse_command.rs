@@ -1,5 +1,7 @@
 use nom::{
     IResult,
+    branch::*,
+    bytes::complete::*,
     combinator::*,
     sequence::*,
     character::complete::{self as character, *},
@@ -8,6 +10,9 @@ use nom::{
 };
 
 use super::{
+    seimcolon_comment,
+    Comment,
+    ExtendedCommand,
     GCode,
     G,
     M,
@@ -54,3 +59,40 @@ pub fn parse_command<'r>(input: &'r str) -> IResult<&'r str, GCode<'r>> {
         },
     )(input)
 }
+
+/// Klipper's built in commands whose names are a single word.
+const SINGLE_WORD_COMMANDS: [&str; 5] = [
+    "HELP",
+    "PAUSE",
+    "RESTART",
+    "RESUME",
+    "STATUS",
+];
+
+/// Whether a word is the name of a Klipper-style extended command: upper case letters, digits and
+/// underscores (eg. "SET_PRESSURE_ADVANCE"). Names without an underscore are only accepted for
+/// Klipper's single word commands so that malformed GCode (eg. "GX10") is not mistaken for them.
+fn is_extended_command_name(name: &str) -> bool {
+    let is_word = name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+
+    is_word && (name.contains('_') || SINGLE_WORD_COMMANDS.contains(&name))
+}
+
+/// Parses a Klipper-style extended command (eg. "SET_PRESSURE_ADVANCE ADVANCE=0.04") and its
+/// comment, if any.
+pub fn parse_extended_command<'r>(input: &'r str) -> IResult<&'r str, ExtendedCommand<'r>> {
+    let name = verify(
+        take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+        is_extended_command_name,
+    );
+
+    map(
+        tuple((
+            terminated(name, peek(alt((space1, tag(";"), line_ending, eof)))),
+            map(take_till(|c| c == ';' || c == '\n' || c == '\r'), str::trim),
+            opt(map(seimcolon_comment, Comment)),
+        )),
+        |(name, args, comment)| ExtendedCommand { name, args, comment },
+    )(input)
+}
@@ -7,7 +7,8 @@ use nom::branch::*;
 use nom::combinator::*;
 use nom::sequence::*;
 use nom::multi::*;
-use std::time::Duration;
+use alloc::vec::Vec;
+use core::time::Duration;
 
 use super::{
     Comment,
@@ -15,7 +16,7 @@ use super::{
 };
 
 // #[inline(always)]
-pub fn parentheses_comment<'r>(input: &'r str) -> IResult<&'r str, &'r str> {
+pub fn parentheses_comment(input: &str) -> IResult<&str, &str> {
     let parser = preceded(
         char('('),
         is_not("\n\r)"),
@@ -56,7 +57,7 @@ pub fn with_parentheses_comments<
             // Merge all comments into one optional vec
             if let Some(more_comments) = more_comments {
                 comments = Some([
-                    comments.unwrap_or_else(|| vec![]),
+                    comments.unwrap_or_default(),
                     more_comments,
                 ].concat())
             }
@@ -70,7 +71,7 @@ pub fn with_parentheses_comments<
 }
 
 // #[inline(always)]
-pub fn seimcolon_comment<'r>(input: &'r str,) -> IResult<&'r str, &'r str> {
+pub fn seimcolon_comment(input: &str,) -> IResult<&str, &str> {
     preceded(
         char(';'),
         not_line_ending,
@@ -81,12 +82,17 @@ pub fn seimcolon_comment<'r>(input: &'r str,) -> IResult<&'r str, &'r str> {
 pub fn comment<'r>(input: &'r str) -> IResult<&'r str, Comment<'r>> {
     map(
         alt((seimcolon_comment, parentheses_comment)),
-        |comment| Comment(comment),
+        Comment,
     )(input)
 }
 
 // #[inline(always)]
 pub fn doc_comment<'r>(input: &'r str) -> IResult<&'r str, DocComment<'r>> {
+    alt((key_value_doc_comment, layer_change))(input)
+}
+
+// #[inline(always)]
+fn key_value_doc_comment<'r>(input: &'r str) -> IResult<&'r str, DocComment<'r>> {
     map_opt(
         preceded(
             char(';'),
@@ -105,6 +111,10 @@ pub fn doc_comment<'r>(input: &'r str) -> IResult<&'r str, DocComment<'r>> {
                 "TIME" => DocComment::PrintTime(Duration::from_secs(value.parse().ok()?)),
                 "Filament used" => filament_used(value).ok()?.1,
                 "Layer height" => DocComment::LayerHeight { millis: value.parse().ok()? },
+                "LAYER" => DocComment::Layer(value.trim_end().parse().ok()?),
+                "Z" => DocComment::LayerZ { millis: value.trim_end().parse().ok()? },
+                "HEIGHT" => DocComment::LayerThickness { millis: value.trim_end().parse().ok()? },
+                "TYPE" => DocComment::FeatureType(value.trim_end()),
                 _ => return None
             };
 
@@ -113,6 +123,14 @@ pub fn doc_comment<'r>(input: &'r str) -> IResult<&'r str, DocComment<'r>> {
     )(input)
 }
 
+// #[inline(always)]
+fn layer_change<'r>(input: &'r str) -> IResult<&'r str, DocComment<'r>> {
+    value(
+        DocComment::LayerChange,
+        tuple((tag(";LAYER_CHANGE"), space0, peek(alt((line_ending, eof))))),
+    )(input)
+}
+
 // #[inline(always)]
 pub fn filament_used<'r>(input: &'r str,) -> IResult<&'r str, DocComment<'r>> {
     map_opt(
@@ -5,9 +5,11 @@ use nom::{
 use nom::branch::*;
 use nom::combinator::*;
 use nom::sequence::*;
+use alloc::string::ToString;
 
 use super::{
     parse_command,
+    parse_extended_command,
     parse_args,
     comment,
     doc_comment,
@@ -30,9 +32,17 @@ const STRING_ARG_MCODES: [u32; 7] = [
     118,
 ];
 
+/// The UTF-8 byte order mark some editors write at the start of a file.
+pub(crate) const BYTE_ORDER_MARK: char = '\u{feff}';
+
+/// Parses a single line of GCode.
+///
+/// A UTF-8 byte order mark at the start of the input (ie. at the start of the file) is skipped.
 // #[inline(always)]
-pub fn parse_gcode(input: &str) -> Result<(&str, Option<GCodeLine>), GCodeParseError> {
+pub fn parse_gcode(input: &str) -> Result<(&str, Option<GCodeLine<'_>>), GCodeParseError> {
     let original_input = input;
+    let input = input.strip_prefix(BYTE_ORDER_MARK).unwrap_or(input);
+
     let demarcator = map(
         pair(char('%'), not_line_ending),
         |_: (char, &str)| GCodeLine::FileDemarcator,
@@ -59,11 +69,11 @@ pub fn parse_gcode(input: &str) -> Result<(&str, Option<GCodeLine>), GCodeParseE
                 // Demarcator (eg. "%")
                 demarcator,
                 // Doc Comment Line (eg. ";TIME:3600")
-                map(doc_comment, |doc| GCodeLine::DocComment(doc)),
+                map(doc_comment, GCodeLine::DocComment),
                 // Comment Line (eg. "; Comment")
-                map(comment, |comment| GCodeLine::Comment(comment))
+                map(comment, GCodeLine::Comment)
             )),
-            |line| Some(line),
+            Some,
         ),
     ));
 
@@ -75,10 +85,18 @@ pub fn parse_gcode(input: &str) -> Result<(&str, Option<GCodeLine>), GCodeParseE
      * Parse the GCode command (eg. this would parse "G1" out of "G1 X10")
      */
 
-    let (input, mut gcode) = parse_command(input)
-        .map_err(|_|
-            GCodeParseError::InvalidGCode(original_input.to_string())
-        )?;
+    let (input, mut gcode) = match parse_command(input) {
+        Ok(parsed) => parsed,
+        // Otherwise the line may be an extended command (eg. "SET_PRESSURE_ADVANCE ADVANCE=0.04")
+        Err(_) => {
+            let (input, command) = parse_extended_command(input)
+                .map_err(|_|
+                    GCodeParseError::InvalidGCode(original_input.to_string())
+                )?;
+
+            return Ok((input, Some(GCodeLine::ExtendedCommand(command))));
+        }
+    };
 
     /*
      * Parse the GCode args (eg. this would parse "X10" out of "G1 X10")
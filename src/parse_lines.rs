@@ -0,0 +1,190 @@
+use core::ops::Range;
+
+use super::{
+    parse_gcode,
+    GCodeLine,
+    GCodeParseError,
+    GCodeParseError::*,
+};
+
+use alloc::string::String;
+#[cfg(feature = "rayon")]
+use alloc::vec::Vec;
+
+/// A line of a GCode file and its position in the file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedLine<'r> {
+    /// The 1-based line number in the file. Not to be confused with the optional `N` line number
+    /// of a [crate::GCode].
+    pub number: usize,
+    /// The byte range of the line in the input, excluding the line ending.
+    pub span: Range<usize>,
+    /// The line's text, excluding the line ending.
+    pub text: &'r str,
+    /// The parsed line, or None for blank lines.
+    pub gcode_line: Option<GCodeLine<'r>>,
+}
+
+/// An owned line of GCode, eg. received from an async reader.
+///
+/// The line is validated when it is created so [OwnedGCodeLine::gcode_line] re-parses it
+/// without failing.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedGCodeLine {
+    /// The 1-based line number in the file.
+    pub number: usize,
+    text: String,
+}
+
+impl OwnedGCodeLine {
+    pub fn new(number: usize, text: String) -> Result<Self, GCodeParseError> {
+        parse_line(&text)?;
+
+        Ok(Self {
+            number,
+            text,
+        })
+    }
+
+    /// The line's text, excluding the line ending.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The parsed line, or None for blank lines.
+    pub fn gcode_line(&self) -> Option<GCodeLine<'_>> {
+        parse_line(&self.text)
+            .expect("OwnedGCodeLines are validated on creation")
+    }
+}
+
+/// Parses a line without its line ending, failing if any of it is left unparsed.
+pub(crate) fn parse_line(text: &str) -> Result<Option<GCodeLine<'_>>, GCodeParseError> {
+    let (remainder, gcode_line) = parse_gcode(text)?;
+
+    if remainder.trim().is_empty() {
+        Ok(gcode_line)
+    } else {
+        Err(InvalidArguments(text.into()))
+    }
+}
+
+/// The line ending used by a program.
+pub(crate) fn line_ending(input: &str) -> &'static str {
+    if input.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+/// An iterator over the parsed lines of a GCode file. See [parse_lines].
+#[derive(Debug, Clone)]
+pub struct Lines<'r> {
+    input: &'r str,
+    offset: usize,
+    end: usize,
+    number: usize,
+}
+
+/// Parses every line of a GCode file.
+///
+/// Invalid lines produce an error but do not stop the iteration.
+pub fn parse_lines(input: &str) -> Lines<'_> {
+    Lines {
+        input,
+        offset: 0,
+        end: input.len(),
+        number: 0,
+    }
+}
+
+impl<'r> Iterator for Lines<'r> {
+    type Item = Result<ParsedLine<'r>, GCodeParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.end {
+            return None
+        }
+
+        let start = self.offset;
+        let line_end = self.input[start..self.end]
+            .find('\n')
+            .map(|i| start + i)
+            .unwrap_or(self.end);
+
+        self.offset = line_end + 1;
+        self.number += 1;
+
+        let text = &self.input[start..line_end];
+        let text = text.strip_suffix('\r').unwrap_or(text);
+
+        let parsed_line = parse_line(text)
+            .map(|gcode_line| ParsedLine {
+                number: self.number,
+                span: start..start + text.len(),
+                text,
+                gcode_line,
+            });
+
+        Some(parsed_line)
+    }
+}
+
+/// Parses every line of a GCode file in parallel, returning the lines in their original order.
+///
+/// The input is split into newline-aligned chunks which are parsed on the rayon thread pool. Large
+/// files can be memory mapped and passed in as a `&str` to avoid reading them into memory first.
+#[cfg(feature = "rayon")]
+pub fn par_parse_lines(input: &str) -> Vec<Result<ParsedLine<'_>, GCodeParseError>> {
+    use rayon::prelude::*;
+
+    // Several chunks per thread so that uneven chunks are balanced by work stealing
+    let chunk_count = rayon::current_num_threads() * 4;
+    let target_chunk_len = (input.len() / chunk_count).max(1);
+
+    let mut chunks = Vec::with_capacity(chunk_count);
+    let mut chunk_start = 0;
+
+    while chunk_start < input.len() {
+        let chunk_end = input.as_bytes()[chunk_start..]
+            .iter()
+            .skip(target_chunk_len)
+            .position(|byte| *byte == b'\n')
+            .map(|i| chunk_start + target_chunk_len + i + 1)
+            .unwrap_or(input.len());
+
+        chunks.push(Lines {
+            input,
+            offset: chunk_start,
+            end: chunk_end,
+            number: 0,
+        });
+
+        chunk_start = chunk_end;
+    }
+
+    let parsed_chunks: Vec<Vec<_>> = chunks
+        .into_par_iter()
+        .map(Iterator::collect)
+        .collect();
+
+    // Line numbers are relative to the start of each chunk until they are offset here
+    let mut lines = Vec::with_capacity(parsed_chunks.iter().map(Vec::len).sum());
+    let mut previous_lines = 0;
+
+    for chunk in parsed_chunks {
+        let chunk_len = chunk.len();
+
+        lines.extend(chunk.into_iter().map(|line| {
+            line.map(|mut line| {
+                line.number += previous_lines;
+                line
+            })
+        }));
+
+        previous_lines += chunk_len;
+    }
+
+    lines
+}
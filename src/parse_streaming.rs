@@ -0,0 +1,54 @@
+use nom::{
+    character::streaming::*,
+    error::Error,
+    sequence::*,
+    Err,
+    Needed,
+};
+
+use super::{
+    parse_lines::parse_line,
+    GCodeLine,
+    GCodeParseError,
+};
+
+/// An error of [parse_gcode_streaming]: either `Incomplete`, or an invalid line's error along
+/// with the input after the invalid line.
+pub type StreamingError<'r> = Err<(&'r str, GCodeParseError)>;
+
+/// Parses a single line of GCode from a partially received input (eg. a serial receive buffer).
+///
+/// Returns `Err(nom::Err::Incomplete(_))` if the line's line ending has not been received yet. On
+/// success the remainder starts after the line ending.
+///
+/// If the line is invalid `Err(nom::Err::Error((remainder, err)))` is returned, where the
+/// remainder also starts after the line ending so that the caller can discard the invalid line
+/// and carry on parsing.
+// #[inline(always)]
+pub fn parse_gcode_streaming(
+    input: &str,
+) -> Result<(&str, Option<GCodeLine<'_>>), StreamingError<'_>> {
+    let (remainder, line) = match terminated(
+        not_line_ending::<_, Error<&str>>,
+        line_ending,
+    )(input) {
+        Ok(parsed) => parsed,
+        Err(Err::Incomplete(needed)) => return Err(Err::Incomplete(needed)),
+        Err(_) => {
+            // A carriage return without a newline inside the line. The line ends at the next
+            // newline, which may not have been received yet.
+            let end = input
+                .find('\n')
+                .ok_or(Err::Incomplete(Needed::Unknown))?;
+
+            let err = GCodeParseError::InvalidGCode(input[..end].into());
+
+            return Err(Err::Error((&input[end + 1..], err)));
+        }
+    };
+
+    let gcode_line = parse_line(line)
+        .map_err(|err| Err::Error((remainder, err)))?;
+
+    Ok((remainder, gcode_line))
+}
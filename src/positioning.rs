@@ -0,0 +1,162 @@
+use super::{
+    parse_lines,
+    rewrite::{rewrite_lines, round_value, with_comments},
+    Axis,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    MachineState,
+    Mnemonic::*,
+    Positioning,
+};
+
+fn positioning_command(positioning: Positioning) -> GCode<'static> {
+    match positioning {
+        Positioning::Absolute => GCode::new(General, 90, 0),
+        Positioning::Relative => GCode::new(General, 91, 0),
+    }
+}
+
+fn extruder_positioning_command(positioning: Positioning) -> GCode<'static> {
+    match positioning {
+        Positioning::Absolute => GCode::new(Miscellaneous, 82, 0),
+        Positioning::Relative => GCode::new(Miscellaneous, 83, 0),
+    }
+}
+
+/// Rewrites a program to use absolute (G90) or relative (G91) moves and absolute (M82) or
+/// relative (M83) extrusion throughout, for tools that only understand one mode. Comments are
+/// kept.
+///
+/// G92 offsets are resolved into the positions of the rewritten moves and the G92 (and G92.1)
+/// commands are removed, so absolute extrusion keeps increasing rather than being reset. Arc
+/// offsets (I/J/K) are always relative to the start of the arc so are left unchanged.
+///
+/// As in Marlin, relative moves (G91) also make extrusion relative regardless of
+/// `extruder_positioning`.
+pub fn convert_positioning(
+    input: &str,
+    positioning: Positioning,
+    extruder_positioning: Positioning,
+) -> Result<String, GCodeParseError> {
+    let relative_extrusion =
+        positioning == Positioning::Relative || extruder_positioning == Positioning::Relative;
+
+    // Programs rely on the default absolute modes until they set them
+    let (sets_positioning, sets_extruder_positioning) = modes_set_before_first_move(input)?;
+    let needs_positioning = positioning != Positioning::Absolute && !sets_positioning;
+    let needs_extruder_positioning =
+        extruder_positioning != Positioning::Absolute && !sets_extruder_positioning;
+
+    let mut is_first_gcode = true;
+
+    rewrite_lines(input, |line, state| {
+        let gcode = match &line.gcode_line {
+            Some(GCodeLine::GCode(gcode)) => gcode,
+            _ => return None,
+        };
+
+        let command = (gcode.mnemonic, gcode.major, gcode.minor);
+
+        let is_positioning_command = matches!(command, (General, 90, 0) | (General, 91, 0));
+        let is_extruder_positioning_command =
+            matches!(command, (Miscellaneous, 82, 0) | (Miscellaneous, 83, 0));
+
+        let mut lines = vec![];
+
+        if is_first_gcode {
+            if needs_positioning {
+                lines.push(positioning_command(positioning).to_string());
+            }
+
+            if needs_extruder_positioning {
+                lines.push(extruder_positioning_command(extruder_positioning).to_string());
+            }
+
+            is_first_gcode = false;
+        }
+
+        let replacement = match command {
+            _ if is_positioning_command => {
+                Some(with_comments(&positioning_command(positioning), line.text))
+            }
+            _ if is_extruder_positioning_command => {
+                Some(with_comments(&extruder_positioning_command(extruder_positioning), line.text))
+            }
+            (General, 0..=3, 0) => {
+                let converted = convert_move(state, gcode, positioning, relative_extrusion);
+                Some(with_comments(&converted, line.text))
+            }
+            (General, 92, 0) | (General, 92, 1) => Some(String::new()),
+            _ => None,
+        };
+
+        if lines.is_empty() {
+            return replacement;
+        }
+
+        lines.push(replacement.unwrap_or_else(|| line.text.to_string()));
+
+        Some(lines.join("\n"))
+    })
+}
+
+/// Whether a program sets its positioning (G90/G91) and extruder positioning (M82/M83) modes
+/// before its first move.
+fn modes_set_before_first_move(input: &str) -> Result<(bool, bool), GCodeParseError> {
+    let mut modes = (false, false);
+
+    for line in parse_lines(input) {
+        let gcode = match line?.gcode_line {
+            Some(GCodeLine::GCode(gcode)) => gcode,
+            _ => continue,
+        };
+
+        match (gcode.mnemonic, gcode.major, gcode.minor) {
+            (General, 0..=3, 0) => break,
+            (General, 90, 0) | (General, 91, 0) => modes.0 = true,
+            (Miscellaneous, 82, 0) | (Miscellaneous, 83, 0) => modes.1 = true,
+            _ => {}
+        }
+    }
+
+    Ok(modes)
+}
+
+/// Rewrites the axes of a move as absolute positions (including any G92 offset) or relative
+/// distances.
+fn convert_move<'r>(
+    state: &MachineState,
+    gcode: &GCode<'r>,
+    positioning: Positioning,
+    relative_extrusion: bool,
+) -> GCode<'r> {
+    let from = state.position;
+    let to = state.target(gcode);
+
+    let mut converted = gcode.clone();
+
+    for axis in Axis::ALL.iter().copied() {
+        if gcode.argument(axis.letter()).is_none() {
+            continue;
+        }
+
+        let i = axis.index();
+
+        let relative = if axis == Axis::E {
+            relative_extrusion
+        } else {
+            positioning == Positioning::Relative
+        };
+
+        let millis = if relative {
+            to[i] - from[i]
+        } else {
+            to[i] + state.position_offset[i]
+        };
+
+        converted.set_argument(axis.letter(), Some(round_value(state.units.from_millimeters(millis))));
+    }
+
+    converted
+}
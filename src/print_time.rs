@@ -0,0 +1,505 @@
+use std::time::Duration;
+
+use super::{
+    parse_lines,
+    toolpath::apply_move,
+    Axis,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    MachineState,
+    Mnemonic::*,
+    Segment,
+    SegmentKind,
+};
+
+/// The motion limits of a printer. The defaults are Marlin's defaults, and the limits are updated
+/// by M201, M203, M204 and M205 commands in the program.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MotionLimits {
+    /// The maximum feedrate of the X, Y, Z and E axes in millimetres per second (M203).
+    pub max_feedrate: [f64; 4],
+    /// The maximum acceleration of the X, Y, Z and E axes in mm/s² (M201).
+    pub max_acceleration: [f64; 4],
+    /// The acceleration of printing moves in mm/s² (M204 P or S).
+    pub acceleration: f64,
+    /// The acceleration of retractions in mm/s² (M204 R).
+    pub retract_acceleration: f64,
+    /// The acceleration of travel moves in mm/s² (M204 T or S).
+    pub travel_acceleration: f64,
+    /// The junction deviation in millimetres (M205 J). Classic jerk (max_jerk) is used to limit
+    /// cornering speeds when this is None.
+    pub junction_deviation: Option<f64>,
+    /// The maximum instantaneous speed change of the X, Y, Z and E axes in millimetres per second
+    /// (M205 X, Y, Z and E).
+    pub max_jerk: [f64; 4],
+    /// The minimum feedrate of printing moves in millimetres per second (M205 S).
+    pub min_feedrate: f64,
+    /// The minimum feedrate of travel moves in millimetres per second (M205 T).
+    pub min_travel_feedrate: f64,
+}
+
+impl Default for MotionLimits {
+    fn default() -> Self {
+        Self {
+            max_feedrate: [300.0, 300.0, 5.0, 25.0],
+            max_acceleration: [3000.0, 3000.0, 100.0, 10000.0],
+            acceleration: 3000.0,
+            retract_acceleration: 3000.0,
+            travel_acceleration: 3000.0,
+            junction_deviation: Some(0.013),
+            max_jerk: [10.0, 10.0, 0.3, 5.0],
+            min_feedrate: 0.0,
+            min_travel_feedrate: 0.0,
+        }
+    }
+}
+
+impl MotionLimits {
+    /// Updates the limits with an M201, M203, M204 or M205 command. Other GCodes are ignored.
+    ///
+    /// Feedrates and accelerations that are not positive (eg. `M204 S0`) would stop every move
+    /// so they are ignored.
+    pub fn apply(&mut self, gcode: &GCode) {
+        let positive = |letter: char| gcode.value(letter).filter(|value| *value > 0.0);
+
+        let set_axes = |values: &mut [f64; 4], value: &dyn Fn(char) -> Option<f64>| {
+            for axis in Axis::ALL.iter().copied() {
+                if let Some(value) = value(axis.letter()) {
+                    values[axis.index()] = value;
+                }
+            }
+        };
+
+        match (gcode.mnemonic, gcode.major, gcode.minor) {
+            (Miscellaneous, 201, 0) => set_axes(&mut self.max_acceleration, &positive),
+            (Miscellaneous, 203, 0) => set_axes(&mut self.max_feedrate, &positive),
+            (Miscellaneous, 204, 0) => {
+                if let Some(acceleration) = positive('S') {
+                    self.acceleration = acceleration;
+                    self.travel_acceleration = acceleration;
+                }
+                if let Some(acceleration) = positive('P') {
+                    self.acceleration = acceleration;
+                }
+                if let Some(acceleration) = positive('R') {
+                    self.retract_acceleration = acceleration;
+                }
+                if let Some(acceleration) = positive('T') {
+                    self.travel_acceleration = acceleration;
+                }
+            }
+            (Miscellaneous, 205, 0) => {
+                set_axes(&mut self.max_jerk, &|letter| gcode.value(letter));
+
+                if let Some(junction_deviation) = gcode.value('J') {
+                    self.junction_deviation = Some(junction_deviation);
+                }
+                if let Some(feedrate) = gcode.value('S') {
+                    self.min_feedrate = feedrate;
+                }
+                if let Some(feedrate) = gcode.value('T') {
+                    self.min_travel_feedrate = feedrate;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The estimated duration of a layer, identified by the Z height it is printed at.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LayerTime {
+    pub z: f64,
+    pub duration: Duration,
+}
+
+/// The result of [estimate_print_time].
+#[derive(Debug, PartialEq, Clone)]
+pub struct PrintTimeEstimate {
+    pub total: Duration,
+    /// The time from the start of the print to the end of each move or dwell, by 1-based line
+    /// number.
+    pub line_times: Vec<(usize, Duration)>,
+    /// The time spent on each layer. A layer starts with its first extruding move at a new Z
+    /// height.
+    pub layer_times: Vec<LayerTime>,
+}
+
+/// A move (or dwell) planned with constant acceleration.
+#[derive(Debug, Clone)]
+struct Block {
+    source_line: usize,
+    /// The length of the move in millimetres. Zero for dwells.
+    length: f64,
+    /// The requested speed in mm/s.
+    nominal_speed: f64,
+    acceleration: f64,
+    /// The cornering limits at the start of the block.
+    junction_deviation: Option<f64>,
+    max_jerk: [f64; 4],
+    /// The direction of travel at the start and end of the move (which only differ for arcs).
+    start_direction: [f64; 4],
+    end_direction: [f64; 4],
+    max_entry_speed: f64,
+    entry_speed: f64,
+    /// The time of a dwell (G4) in seconds.
+    dwell: f64,
+    /// The Z height of extruding moves.
+    extruding_z: Option<f64>,
+}
+
+fn normalize(mut vector: [f64; 4]) -> [f64; 4] {
+    let length = vector.iter().map(|v| v * v).sum::<f64>().sqrt();
+
+    if length > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= length);
+    }
+
+    vector
+}
+
+/// The direction of travel at the start and end of a segment.
+fn directions(segment: &Segment) -> ([f64; 4], [f64; 4]) {
+    let mut delta = segment.delta();
+
+    // Only retractions and primes move along the E axis alone
+    if segment.distance() > 0.0 {
+        delta[Axis::E.index()] = 0.0;
+    }
+
+    match segment.kind {
+        SegmentKind::Arc { clockwise, center, plane } => {
+            let [a, b, _] = plane.axes();
+            let (a, b) = (a.index(), b.index());
+
+            let tangent = |point: &[f64; 4]| {
+                let (ra, rb) = (point[a] - center[a], point[b] - center[b]);
+                let mut tangent = [0.0; 4];
+
+                if clockwise {
+                    tangent[a] = rb;
+                    tangent[b] = -ra;
+                } else {
+                    tangent[a] = -rb;
+                    tangent[b] = ra;
+                }
+
+                normalize(tangent)
+            };
+
+            (tangent(&segment.from), tangent(&segment.to))
+        }
+        _ => {
+            let direction = normalize(delta);
+            (direction, direction)
+        }
+    }
+}
+
+fn plan_move(segment: &Segment, state: &MachineState, limits: &MotionLimits) -> Block {
+    let xyz_length = segment.length();
+    let e_only = xyz_length == 0.0;
+    let length = if e_only { segment.extrusion.abs() } else { xyz_length };
+
+    let mut nominal_speed = state.feedrate
+        .map(|feedrate| feedrate / 60.0)
+        .unwrap_or(limits.max_feedrate[0]);
+
+    let min_feedrate = if segment.extrusion != 0.0 {
+        limits.min_feedrate
+    } else {
+        limits.min_travel_feedrate
+    };
+
+    nominal_speed = nominal_speed.max(min_feedrate);
+
+    let mut acceleration = if e_only {
+        limits.retract_acceleration
+    } else if segment.extrusion > 0.0 {
+        limits.acceleration
+    } else {
+        limits.travel_acceleration
+    };
+
+    // Limit the speed and acceleration so that no axis exceeds its own limits
+    for (i, delta) in segment.delta().iter().enumerate() {
+        let delta = delta.abs();
+
+        // Limits that are not positive are ignored, as by MotionLimits::apply
+        if delta > 0.0 && limits.max_feedrate[i] > 0.0 {
+            nominal_speed = nominal_speed.min(limits.max_feedrate[i] * length / delta);
+        }
+        if delta > 0.0 && limits.max_acceleration[i] > 0.0 {
+            acceleration = acceleration.min(limits.max_acceleration[i] * length / delta);
+        }
+    }
+
+    let (start_direction, end_direction) = directions(segment);
+
+    Block {
+        source_line: segment.source_line,
+        length,
+        nominal_speed,
+        acceleration,
+        junction_deviation: limits.junction_deviation,
+        max_jerk: limits.max_jerk,
+        start_direction,
+        end_direction,
+        max_entry_speed: 0.0,
+        entry_speed: 0.0,
+        dwell: 0.0,
+        extruding_z: if segment.kind != SegmentKind::Travel && segment.extrusion > 0.0 {
+            Some(segment.to[Axis::Z.index()])
+        } else {
+            None
+        },
+    }
+}
+
+/// The maximum speed at the junction between two moves.
+fn max_junction_speed(previous: &Block, block: &Block) -> f64 {
+    if previous.length == 0.0 || block.length == 0.0 {
+        return 0.0;
+    }
+
+    let nominal_speed = previous.nominal_speed.min(block.nominal_speed);
+
+    match block.junction_deviation {
+        Some(junction_deviation) => {
+            let cos_theta = -(0..4)
+                .map(|i| previous.end_direction[i] * block.start_direction[i])
+                .sum::<f64>();
+
+            // Reversals come to a stop and straight lines do not slow down
+            if cos_theta > 0.999_999 {
+                return 0.0;
+            }
+            if cos_theta < -0.999_999 {
+                return nominal_speed;
+            }
+
+            let sin_theta_d2 = (0.5 * (1.0 - cos_theta)).sqrt();
+            let speed_squared = block.acceleration * junction_deviation * sin_theta_d2
+                / (1.0 - sin_theta_d2);
+
+            speed_squared.sqrt().min(nominal_speed)
+        }
+        None => {
+            // Classic jerk: slow down until no axis changes speed by more than its jerk limit
+            let mut factor: f64 = 1.0;
+
+            for i in 0..4 {
+                let jump = (previous.end_direction[i] - block.start_direction[i]).abs() * nominal_speed;
+
+                if jump > block.max_jerk[i] {
+                    factor = factor.min(block.max_jerk[i] / jump);
+                }
+            }
+
+            nominal_speed * factor
+        }
+    }
+}
+
+/// The time to travel a block accelerating from its entry speed to its nominal speed (or as
+/// close as it can get) and decelerating to its exit speed.
+fn block_time(block: &Block, exit_speed: f64) -> f64 {
+    if block.length == 0.0 {
+        return block.dwell;
+    }
+
+    // Moves without a speed (eg. with every limit set to zero) cannot be timed
+    if block.nominal_speed <= 0.0 {
+        return 0.0;
+    }
+
+    // Without an acceleration limit moves change speed instantly
+    if block.acceleration <= 0.0 {
+        return block.length / block.nominal_speed;
+    }
+
+    let (entry, exit, nominal, acceleration) = (
+        block.entry_speed,
+        exit_speed,
+        block.nominal_speed,
+        block.acceleration,
+    );
+
+    let accelerate_distance = (nominal.powi(2) - entry.powi(2)) / (2.0 * acceleration);
+    let decelerate_distance = (nominal.powi(2) - exit.powi(2)) / (2.0 * acceleration);
+
+    if accelerate_distance + decelerate_distance <= block.length {
+        let cruise_distance = block.length - accelerate_distance - decelerate_distance;
+
+        (nominal - entry) / acceleration + (nominal - exit) / acceleration + cruise_distance / nominal
+    } else {
+        // A triangular profile that never reaches the nominal speed
+        let peak = ((2.0 * acceleration * block.length + entry.powi(2) + exit.powi(2)) / 2.0)
+            .sqrt()
+            .max(entry)
+            .max(exit);
+
+        (peak - entry) / acceleration + (peak - exit) / acceleration
+    }
+}
+
+/// Calculates the entry speed of each block with look-ahead across the whole program, coming to a
+/// stop at the start and end of the program and at each dwell.
+fn plan_speeds(blocks: &mut [Block]) {
+    for i in 1..blocks.len() {
+        let max_entry_speed = max_junction_speed(&blocks[i - 1], &blocks[i]);
+        blocks[i].max_entry_speed = max_entry_speed;
+    }
+
+    // Backward pass: make sure every block can decelerate to the entry speed of the next one
+    let mut next_entry_speed: f64 = 0.0;
+
+    for block in blocks.iter_mut().rev() {
+        let acceleration = block.acceleration.max(0.0);
+        let reachable = (next_entry_speed.powi(2) + 2.0 * acceleration * block.length).sqrt();
+
+        block.entry_speed = block.max_entry_speed.min(reachable);
+        next_entry_speed = block.entry_speed;
+    }
+
+    // Forward pass: make sure every block can accelerate to the entry speed of the next one
+    for i in 1..blocks.len() {
+        let previous = &blocks[i - 1];
+        let reachable = (previous.entry_speed.powi(2)
+            + 2.0 * previous.acceleration.max(0.0) * previous.length).sqrt();
+
+        blocks[i].entry_speed = blocks[i].entry_speed.min(reachable);
+    }
+}
+
+/// Converts a time in seconds to a Duration, saturating rather than panicking on times that are
+/// too long (or not a number).
+fn duration(seconds: f64) -> Duration {
+    if seconds > 0.0 {
+        Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX)
+    } else {
+        Duration::ZERO
+    }
+}
+
+/// Plans the moves and dwells of a program one line at a time. See [estimate_print_time].
+pub(crate) struct PrintTimePlanner {
+    limits: MotionLimits,
+    state: MachineState,
+    blocks: Vec<Block>,
+}
+
+impl PrintTimePlanner {
+    pub(crate) fn new(limits: &MotionLimits) -> Self {
+        Self {
+            limits: limits.clone(),
+            state: MachineState::new(),
+            blocks: vec![],
+        }
+    }
+
+    /// Adds a GCode to the plan, returning the segment it moves along if it is a move.
+    pub(crate) fn push(&mut self, gcode: &GCode, line_number: usize) -> Option<Segment> {
+        let limits = &mut self.limits;
+        limits.apply(gcode);
+
+        if let (General, 4, 0) = (gcode.mnemonic, gcode.major, gcode.minor) {
+            let dwell = gcode.value('S').unwrap_or(0.0) + gcode.value('P').unwrap_or(0.0) / 1000.0;
+            let dwell = dwell.max(0.0);
+
+            self.blocks.push(Block {
+                source_line: line_number,
+                length: 0.0,
+                nominal_speed: 0.0,
+                acceleration: 0.0,
+                junction_deviation: limits.junction_deviation,
+                max_jerk: limits.max_jerk,
+                start_direction: [0.0; 4],
+                end_direction: [0.0; 4],
+                max_entry_speed: 0.0,
+                entry_speed: 0.0,
+                dwell,
+                extruding_z: None,
+            });
+
+            return None;
+        }
+
+        // As in Marlin, feedrates that are not positive (eg. "G1 F0") are ignored
+        let segment = match gcode.value('F') {
+            Some(feedrate) if feedrate <= 0.0 => {
+                let mut gcode = gcode.clone();
+                gcode.remove_argument('F');
+
+                apply_move(&mut self.state, &gcode, line_number)?
+            }
+            _ => apply_move(&mut self.state, gcode, line_number)?,
+        };
+        self.blocks.push(plan_move(&segment, &self.state, limits));
+
+        Some(segment)
+    }
+
+    /// Plans the speeds of every move with look-ahead and times them.
+    pub(crate) fn finish(mut self) -> PrintTimeEstimate {
+        let blocks = &mut self.blocks;
+        plan_speeds(blocks);
+
+        let mut total = 0.0;
+        let mut line_times = Vec::with_capacity(blocks.len());
+        let mut layer_times: Vec<LayerTime> = vec![];
+        let mut layer_start = 0.0;
+
+        for (i, block) in blocks.iter().enumerate() {
+            let exit_speed = blocks.get(i + 1).map(|next| next.entry_speed).unwrap_or(0.0);
+
+            if let Some(z) = block.extruding_z {
+                let new_layer = layer_times.last().map(|layer| layer.z != z).unwrap_or(true);
+
+                if new_layer {
+                    if let Some(layer) = layer_times.last_mut() {
+                        layer.duration = duration(total - layer_start);
+                    }
+
+                    layer_start = total;
+                    layer_times.push(LayerTime { z, duration: Duration::default() });
+                }
+            }
+
+            total += block_time(block, exit_speed);
+            line_times.push((block.source_line, duration(total)));
+        }
+
+        if let Some(layer) = layer_times.last_mut() {
+            layer.duration = duration(total - layer_start);
+        }
+
+        PrintTimeEstimate {
+            total: duration(total),
+            line_times,
+            layer_times,
+        }
+    }
+}
+
+/// Estimates the time a program takes to print by simulating a Marlin-like motion planner with
+/// acceleration, junction deviation (or classic jerk) and look-ahead.
+///
+/// Heating (M109/M190) and other waits are not included since their duration is unknown.
+pub fn estimate_print_time(
+    input: &str,
+    limits: &MotionLimits,
+) -> Result<PrintTimeEstimate, GCodeParseError> {
+    let mut planner = PrintTimePlanner::new(limits);
+
+    for line in parse_lines(input) {
+        let line = line?;
+
+        if let Some(GCodeLine::GCode(gcode)) = &line.gcode_line {
+            planner.push(gcode, line.number);
+        }
+    }
+
+    Ok(planner.finish())
+}
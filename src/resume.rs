@@ -0,0 +1,186 @@
+use super::{
+    parse_lines,
+    parse_lines::line_ending,
+    rewrite::round_value,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    LayerIndex,
+    LayerSelector,
+    MachineState,
+    Mnemonic::*,
+    Positioning,
+    Units,
+};
+
+/// Where to resume a program. See [resume_program].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ResumeFrom {
+    /// The 1-based line number of the first line to run.
+    Line(usize),
+    /// The start of a layer.
+    Layer(LayerSelector),
+}
+
+/// Options for [resume_program].
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResumeOptions {
+    /// How far to lift the nozzle off the print (in millimetres) before homing X and Y.
+    pub z_hop: f64,
+}
+
+impl Default for ResumeOptions {
+    fn default() -> Self {
+        Self {
+            z_hop: 2.0,
+        }
+    }
+}
+
+/// Generates a program that resumes a print part way through, eg. after a power loss.
+///
+/// The modal state of the program up to the resume point is tracked and a preamble restores it:
+/// the temperatures are restored and waited for, the current Z height is set with G92 (Z cannot
+/// be homed with a print on the bed), the nozzle is lifted and X and Y are homed, and then the
+/// tool, fan speeds, extruder position, feedrate and positioning modes are restored and the
+/// nozzle is moved back to where the program left off. The rest of the program follows
+/// unchanged.
+///
+/// X and Y G92 offsets are not restored since homing clears them.
+///
+/// Returns None if the resume point is not in the program.
+pub fn resume_program(
+    input: &str,
+    from: ResumeFrom,
+    options: &ResumeOptions,
+) -> Result<Option<String>, GCodeParseError> {
+    let resume_line = match from {
+        ResumeFrom::Line(line) => line,
+        ResumeFrom::Layer(selector) => match LayerIndex::new(input)?.find(selector) {
+            Some(layer) => layer.lines.start,
+            None => return Ok(None),
+        },
+    };
+
+    let mut state = MachineState::new();
+    let mut resume_offset = None;
+
+    for line in parse_lines(input) {
+        let line = line?;
+
+        if line.number == resume_line {
+            resume_offset = Some(line.span.start);
+            break;
+        }
+
+        if let Some(GCodeLine::GCode(gcode)) = &line.gcode_line {
+            state.apply(gcode);
+        }
+    }
+
+    let resume_offset = match resume_offset {
+        Some(offset) => offset,
+        None => return Ok(None),
+    };
+
+    let line_ending = line_ending(input);
+    let mut output = format!("; Resuming from line {}{}", resume_line, line_ending);
+
+    for line in preamble(&state, options) {
+        output.push_str(&line.to_string());
+        output.push_str(line_ending);
+    }
+
+    output.push_str(&input[resume_offset..]);
+
+    Ok(Some(output))
+}
+
+/// The commands that restore a machine state on a machine that has lost power.
+fn preamble(state: &MachineState, options: &ResumeOptions) -> Vec<GCode<'static>> {
+    // Lengths are written in the program's units
+    let length = |millis: f64| Some(round_value(state.units.from_millimeters(millis)));
+
+    let mut commands = vec![
+        match state.units {
+            Units::Millimeters => GCode::new(General, 21, 0),
+            Units::Inches => GCode::new(General, 20, 0),
+        },
+    ];
+
+    // Select the work coordinate system before setting positions in it
+    if state.coordinate_system != 0 {
+        commands.push(match state.coordinate_system {
+            system @ 0..=5 => GCode::new(General, 54 + system as u32, 0),
+            system => GCode::new(General, 59, system as u32 - 5),
+        });
+    }
+
+    // Start heating everything before waiting for any of it
+    if let Some(temperature) = state.bed_temperature {
+        commands.push(GCode::new(Miscellaneous, 140, 0).with_argument('S', Some(temperature)));
+    }
+
+    for (tool, temperature) in &state.hotend_temperatures {
+        commands.push(
+            GCode::new(Miscellaneous, 104, 0)
+                .with_argument('T', Some(*tool as f64))
+                .with_argument('S', Some(*temperature)),
+        );
+    }
+
+    if let Some(temperature) = state.bed_temperature {
+        commands.push(GCode::new(Miscellaneous, 190, 0).with_argument('S', Some(temperature)));
+    }
+
+    for (tool, temperature) in &state.hotend_temperatures {
+        commands.push(
+            GCode::new(Miscellaneous, 109, 0)
+                .with_argument('T', Some(*tool as f64))
+                .with_argument('S', Some(*temperature)),
+        );
+    }
+
+    let [x, y, z, e] = state.position;
+
+    // Set the Z height the print stopped at, lift off the print and home the other axes
+    commands.extend(vec![
+        GCode::new(General, 92, 0).with_argument('Z', length(z)),
+        GCode::new(General, 91, 0),
+        GCode::new(General, 0, 0).with_argument('Z', length(options.z_hop)),
+        GCode::new(General, 90, 0),
+        GCode::new(General, 28, 0).with_argument('X', None).with_argument('Y', None),
+    ]);
+
+    commands.push(GCode::new(ToolChange, state.tool, 0));
+
+    for (fan, speed) in &state.fan_speeds {
+        commands.push(
+            GCode::new(Miscellaneous, 106, 0)
+                .with_argument('P', Some(*fan as f64))
+                .with_argument('S', Some(*speed)),
+        );
+    }
+
+    commands.push(match state.extruder_positioning {
+        Positioning::Absolute => GCode::new(Miscellaneous, 82, 0),
+        Positioning::Relative => GCode::new(Miscellaneous, 83, 0),
+    });
+
+    // Move back to where the program left off
+    commands.extend(vec![
+        GCode::new(General, 92, 0).with_argument('E', length(e)),
+        GCode::new(General, 0, 0).with_argument('X', length(x)).with_argument('Y', length(y)),
+        GCode::new(General, 0, 0).with_argument('Z', length(z)),
+    ]);
+
+    if let Some(feedrate) = state.feedrate {
+        commands.push(GCode::new(General, 1, 0).with_argument('F', length(feedrate)));
+    }
+
+    if state.positioning == Positioning::Relative {
+        commands.push(GCode::new(General, 91, 0));
+    }
+
+    commands
+}
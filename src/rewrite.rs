@@ -0,0 +1,110 @@
+use super::{
+    parse_lines::line_ending,
+    parse_lines,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    MachineState,
+    ParsedLine,
+};
+
+/// The number of decimal places of argument values generated by transformations.
+const DECIMAL_PLACES: i32 = 5;
+
+/// Rounds a generated argument value so that it is serialized without floating point noise (eg.
+/// 0.30000000000000004).
+pub(crate) fn round_value(value: f64) -> f64 {
+    let factor = 10f64.powi(DECIMAL_PLACES);
+    let rounded = (value * factor).round() / factor;
+
+    // Negative zero would be serialized as "-0"
+    if rounded == 0.0 {
+        0.0
+    } else {
+        rounded
+    }
+}
+
+/// The comments of a line (eg. "(part 1) ; perimeter"), separated by spaces.
+fn comments(text: &str) -> String {
+    let mut comments = vec![];
+    let mut escaped = false;
+    let mut chars = text.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ';' => {
+                comments.push(text[i..].trim_end());
+                break;
+            }
+            '(' => {
+                if let Some(end) = text[i..].find(')') {
+                    comments.push(&text[i..=i + end]);
+
+                    // Skip to the end of the comment
+                    chars.by_ref().take_while(|(j, _)| *j < i + end).for_each(drop);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    comments.join(" ")
+}
+
+/// Serializes a rewritten GCode followed by the comments of the line it replaces, which the
+/// GCode's Display implementation would otherwise drop.
+pub(crate) fn with_comments(gcode: &GCode, text: &str) -> String {
+    let comments = comments(text);
+
+    if comments.is_empty() {
+        gcode.to_string()
+    } else {
+        format!("{} {}", gcode, comments)
+    }
+}
+
+/// Rewrites a program line by line.
+///
+/// `rewrite_line` is called with each line and the machine state before that line. It returns
+/// the line's replacement (which may span several lines, or be empty to remove the line) or None
+/// to copy the line unchanged. The state is then updated with the original line.
+///
+/// The input's line endings are preserved.
+pub(crate) fn rewrite_lines<'r, F>(
+    input: &'r str,
+    mut rewrite_line: F,
+) -> Result<String, GCodeParseError>
+where
+    F: FnMut(&ParsedLine<'r>, &MachineState) -> Option<String>,
+{
+    let line_ending = line_ending(input);
+
+    let mut output = String::with_capacity(input.len());
+    let mut state = MachineState::new();
+
+    for line in parse_lines(input) {
+        let line = line?;
+
+        match rewrite_line(&line, &state) {
+            Some(replacement) => {
+                for replacement_line in replacement.lines() {
+                    output.push_str(replacement_line);
+                    output.push_str(line_ending);
+                }
+            }
+            None => {
+                output.push_str(line.text);
+                output.push_str(line_ending);
+            }
+        }
+
+        if let Some(GCodeLine::GCode(gcode)) = &line.gcode_line {
+            state.apply(gcode);
+        }
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,273 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use super::{
+    parse_gcode::BYTE_ORDER_MARK,
+    parse_lines::line_ending,
+    GCodeParseError,
+};
+
+/// The image format of a [Thumbnail].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ThumbnailFormat {
+    Png,
+    Jpg,
+    Qoi,
+}
+
+impl ThumbnailFormat {
+    /// The keyword of the format's thumbnail blocks (eg. "thumbnail_JPG").
+    fn keyword(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Png => "thumbnail",
+            ThumbnailFormat::Jpg => "thumbnail_JPG",
+            ThumbnailFormat::Qoi => "thumbnail_QOI",
+        }
+    }
+
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        [ThumbnailFormat::Png, ThumbnailFormat::Jpg, ThumbnailFormat::Qoi]
+            .iter()
+            .copied()
+            .find(|format| format.keyword() == keyword)
+    }
+}
+
+/// A preview image embedded in a GCode file by the slicer.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub format: ThumbnailFormat,
+    /// The encoded image (eg. the contents of a PNG file).
+    pub bytes: Vec<u8>,
+}
+
+/// The number of base64 characters per line of a thumbnail block, matching PrusaSlicer.
+const THUMBNAIL_LINE_LENGTH: usize = 78;
+
+impl Thumbnail {
+    /// Encodes the thumbnail as a block of comments, eg.
+    ///
+    /// ```text
+    /// ; thumbnail begin 16x16 40
+    /// ; iVBORw0KGgoAAAANSUhEUgAAABAAAAAQCAYAAAAf
+    /// ; thumbnail end
+    /// ```
+    pub fn to_gcode(&self) -> String {
+        let data = base64_encode(&self.bytes);
+        let keyword = self.format.keyword();
+
+        let mut lines = Vec::new();
+        lines.push(format!("; {} begin {}x{} {}", keyword, self.width, self.height, data.len()));
+
+        // Base64 is ASCII so the data can be split at any byte
+        for chunk in data.as_bytes().chunks(THUMBNAIL_LINE_LENGTH) {
+            lines.push(format!("; {}", core::str::from_utf8(chunk).unwrap()));
+        }
+
+        lines.push(format!("; {} end", keyword));
+
+        lines.join("\n")
+    }
+}
+
+/// The header of a thumbnail block (eg. `; thumbnail begin 300x300 12345`), or None if the line
+/// does not start a thumbnail.
+fn thumbnail_begin(line: &str) -> Option<(ThumbnailFormat, u32, u32)> {
+    let mut words = line.trim().strip_prefix(';')?.split_whitespace();
+
+    let format = ThumbnailFormat::from_keyword(words.next()?)?;
+
+    if words.next()? != "begin" {
+        return None;
+    }
+
+    let (width, height) = words.next()?.split_once('x')?;
+
+    Some((format, width.parse().ok()?, height.parse().ok()?))
+}
+
+fn is_thumbnail_end(line: &str, format: ThumbnailFormat) -> bool {
+    let mut words = line
+        .trim()
+        .strip_prefix(';')
+        .unwrap_or_default()
+        .split_whitespace();
+
+    words.next() == Some(format.keyword()) && words.next() == Some("end")
+}
+
+/// Extracts the thumbnails embedded in a GCode file by PrusaSlicer, Cura, OrcaSlicer and others.
+///
+/// Returns an error if a thumbnail is not terminated or its data is not valid base64.
+pub fn thumbnails(input: &str) -> Result<Vec<Thumbnail>, GCodeParseError> {
+    let mut thumbnails = Vec::new();
+    let mut lines = input.lines();
+
+    while let Some(line) = lines.next() {
+        let (format, width, height) = match thumbnail_begin(line) {
+            Some(header) => header,
+            None => continue,
+        };
+
+        let mut data = String::new();
+        let mut terminated = false;
+
+        for data_line in lines.by_ref() {
+            if is_thumbnail_end(data_line, format) {
+                terminated = true;
+                break;
+            }
+
+            let data_line = data_line.trim();
+            data.push_str(data_line.strip_prefix(';').unwrap_or(data_line).trim());
+        }
+
+        if !terminated {
+            return Err(GCodeParseError::InvalidComment(line.to_string()));
+        }
+
+        let bytes = base64_decode(&data)
+            .ok_or_else(|| GCodeParseError::InvalidComment(line.to_string()))?;
+
+        thumbnails.push(Thumbnail {
+            width,
+            height,
+            format,
+            bytes,
+        });
+    }
+
+    Ok(thumbnails)
+}
+
+/// Writes thumbnails into a GCode file.
+///
+/// Existing thumbnails with the same size and format as a new thumbnail are removed, and the new
+/// thumbnails are added to the start of the file where printers look for them. The input's line
+/// endings are preserved.
+///
+/// Returns an error if an existing thumbnail is not terminated, as [thumbnails] does.
+pub fn insert_thumbnails(
+    input: &str,
+    new_thumbnails: &[Thumbnail],
+) -> Result<String, GCodeParseError> {
+    let line_ending = line_ending(input);
+    let mut output = String::with_capacity(input.len());
+
+    // The byte order mark must stay at the start of the file
+    let input = match input.strip_prefix(BYTE_ORDER_MARK) {
+        Some(input) => {
+            output.push(BYTE_ORDER_MARK);
+            input
+        }
+        None => input,
+    };
+
+    for thumbnail in new_thumbnails {
+        for line in thumbnail.to_gcode().lines() {
+            output.push_str(line);
+            output.push_str(line_ending);
+        }
+    }
+
+    let is_replaced = |format, width, height| {
+        new_thumbnails.iter().any(|thumbnail| {
+            (thumbnail.format, thumbnail.width, thumbnail.height) == (format, width, height)
+        })
+    };
+
+    // The format and first line of the thumbnail being copied or removed, and whether it is
+    // removed
+    let mut current: Option<(ThumbnailFormat, &str, bool)> = None;
+
+    for line in input.split_inclusive('\n') {
+        match current {
+            Some((format, _, removing)) => {
+                if is_thumbnail_end(line, format) {
+                    current = None;
+                }
+
+                if removing {
+                    continue;
+                }
+            }
+            None => {
+                if let Some((format, width, height)) = thumbnail_begin(line) {
+                    let removing = is_replaced(format, width, height);
+                    current = Some((format, line, removing));
+
+                    if removing {
+                        continue;
+                    }
+                }
+            }
+        }
+
+        output.push_str(line);
+    }
+
+    if let Some((_, begin, _)) = current {
+        return Err(GCodeParseError::InvalidComment(begin.trim_end().to_string()));
+    }
+
+    Ok(output)
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (n >> (18 - 6 * i)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes standard base64, returning None if the data is not valid base64.
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+
+    if encoded.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+
+    for chunk in encoded.chunks(4) {
+        let mut n = 0u32;
+
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|a| a == c)? as u32;
+            n |= value << (18 - 6 * i);
+        }
+
+        // Each character holds 6 bits, so a partial chunk of n characters holds n - 1 bytes
+        for i in 0..chunk.len() - 1 {
+            bytes.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+
+    Some(bytes)
+}
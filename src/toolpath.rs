@@ -0,0 +1,332 @@
+use super::{
+    parse_lines,
+    Axis,
+    BoundingBox,
+    DocComment,
+    FeatureRole,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    Lines,
+    MachineState,
+    G,
+    Plane,
+};
+
+/// The kind of move a [Segment] represents.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SegmentKind {
+    /// A linear move that does not extrude (including retractions and moves that only prime the
+    /// extruder).
+    Travel,
+    /// A linear move that extrudes.
+    Extrude,
+    /// A G2 (clockwise) or G3 (counter-clockwise) arc around center, in machine coordinates.
+    Arc {
+        clockwise: bool,
+        center: [f64; 3],
+        plane: Plane,
+    },
+}
+
+/// A single move of a program with all positions resolved to absolute machine coordinates in
+/// millimetres.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Segment {
+    /// The X, Y, Z and E position at the start of the move.
+    pub from: [f64; 4],
+    /// The X, Y, Z and E position at the end of the move.
+    pub to: [f64; 4],
+    /// The feedrate in millimetres per minute.
+    pub feedrate: Option<f64>,
+    /// The length of filament extruded by the move. Negative for retractions.
+    pub extrusion: f64,
+    pub kind: SegmentKind,
+    /// The tool that performed the move.
+    pub tool: u32,
+    /// The feature the move prints, from the slicer's most recent `;TYPE:` comment. Only set by
+    /// [toolpath].
+    pub feature: Option<FeatureRole>,
+    /// The 1-based line number of the move in the file.
+    pub source_line: usize,
+}
+
+impl Segment {
+    /// The change in the X, Y, Z and E position.
+    pub fn delta(&self) -> [f64; 4] {
+        let mut delta = self.to;
+
+        for (delta, from) in delta.iter_mut().zip(self.from.iter()) {
+            *delta -= from;
+        }
+
+        delta
+    }
+
+    /// The straight line distance travelled in X, Y and Z.
+    pub fn distance(&self) -> f64 {
+        distance(&self.from, &self.to)
+    }
+
+    /// The distance travelled in X, Y and Z along the path of the move (ie. along the arc for
+    /// arcs).
+    pub fn length(&self) -> f64 {
+        match self.kind {
+            SegmentKind::Arc { clockwise, center, plane } => {
+                let [a, b, normal] = plane.axes();
+                let (a, b, normal) = (a.index(), b.index(), normal.index());
+
+                let start_angle = (self.from[b] - center[b]).atan2(self.from[a] - center[a]);
+                let end_angle = (self.to[b] - center[b]).atan2(self.to[a] - center[a]);
+                let sweep = arc_sweep(start_angle, end_angle, clockwise);
+
+                let start_radius = (self.from[a] - center[a]).hypot(self.from[b] - center[b]);
+                let end_radius = (self.to[a] - center[a]).hypot(self.to[b] - center[b]);
+                let arc_length = sweep.abs() * (start_radius + end_radius) / 2.0;
+
+                arc_length.hypot(self.to[normal] - self.from[normal])
+            }
+            _ => self.distance(),
+        }
+    }
+
+    /// The bounds of the move in X, Y and Z, including the parts of arcs that bulge past their
+    /// end points.
+    pub fn bounding_box(&self) -> BoundingBox {
+        let mut bounds = BoundingBox::from_point([self.from[0], self.from[1], self.from[2]]);
+        bounds.include([self.to[0], self.to[1], self.to[2]]);
+
+        if let SegmentKind::Arc { clockwise, center, plane } = self.kind {
+            use core::f64::consts::FRAC_PI_2;
+
+            let [a, b, _] = plane.axes();
+            let (a, b) = (a.index(), b.index());
+
+            let start_angle = (self.from[b] - center[b]).atan2(self.from[a] - center[a]);
+            let end_angle = (self.to[b] - center[b]).atan2(self.to[a] - center[a]);
+            let sweep = arc_sweep(start_angle, end_angle, clockwise);
+            let radius = (self.from[a] - center[a]).hypot(self.from[b] - center[b]);
+
+            // Add each of the four extremes of the circle that the arc passes through
+            for quadrant in 0..4 {
+                let angle = quadrant as f64 * FRAC_PI_2;
+
+                // The angle swept from the start of the arc to the extreme, in the arc's direction
+                let mut to_extreme = (angle - start_angle).rem_euclid(4.0 * FRAC_PI_2);
+                if clockwise && to_extreme > 0.0 {
+                    to_extreme -= 4.0 * FRAC_PI_2;
+                }
+
+                if to_extreme.abs() <= sweep.abs() {
+                    // The normal axis is linear so the arc's start bounds it
+                    let mut extreme = [self.from[0], self.from[1], self.from[2]];
+                    extreme[a] = center[a] + radius * angle.cos();
+                    extreme[b] = center[b] + radius * angle.sin();
+
+                    bounds.include(extreme);
+                }
+            }
+        }
+
+        bounds
+    }
+}
+
+/// The straight line distance between two positions in X, Y and Z.
+pub(crate) fn distance(from: &[f64; 4], to: &[f64; 4]) -> f64 {
+    (0..3)
+        .map(|i| (to[i] - from[i]).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// The signed angle swept from start_angle to end_angle, negative for clockwise arcs. Arcs that
+/// start and end at the same angle are full circles.
+pub(crate) fn arc_sweep(start_angle: f64, end_angle: f64, clockwise: bool) -> f64 {
+    use core::f64::consts::PI;
+
+    let mut sweep = end_angle - start_angle;
+
+    if clockwise {
+        if sweep >= 0.0 {
+            sweep -= 2.0 * PI;
+        }
+    } else if sweep <= 0.0 {
+        sweep += 2.0 * PI;
+    }
+
+    sweep
+}
+
+/// The center of a G2/G3 arc from `from` to `to`, given either I/J/K offsets from the start of
+/// the arc or a radius (R). Positive radii select the shorter of the two possible arcs and
+/// negative radii the longer one.
+pub(crate) fn arc_center(
+    state: &MachineState,
+    gcode: &GCode,
+    from: &[f64; 4],
+    to: &[f64; 4],
+) -> Option<[f64; 3]> {
+    let [a, b, _] = state.plane.axes();
+    let (a, b) = (a.index(), b.index());
+    let [a_letter, b_letter] = state.plane.offset_letters();
+
+    let mut center = [from[0], from[1], from[2]];
+
+    if let Some(radius) = gcode.value('R') {
+        let radius = state.to_millimeters(radius);
+
+        let (da, db) = (to[a] - from[a], to[b] - from[b]);
+        let chord = da.hypot(db);
+
+        if chord == 0.0 {
+            return None;
+        }
+
+        // Distance from the middle of the chord to the center, clamped for rounding errors
+        let h = (radius.powi(2) - (chord / 2.0).powi(2)).max(0.0).sqrt();
+
+        // Short counter-clockwise arcs have their center to the left of the chord
+        let clockwise = gcode.major == 2;
+        let side = if clockwise { -1.0 } else { 1.0 } * radius.signum();
+
+        center[a] = from[a] + da / 2.0 - db / chord * h * side;
+        center[b] = from[b] + db / 2.0 + da / chord * h * side;
+    } else {
+        let offset_a = gcode.value(a_letter);
+        let offset_b = gcode.value(b_letter);
+
+        if offset_a.is_none() && offset_b.is_none() {
+            return None;
+        }
+
+        center[a] += state.to_millimeters(offset_a.unwrap_or(0.0));
+        center[b] += state.to_millimeters(offset_b.unwrap_or(0.0));
+    }
+
+    Some(center)
+}
+
+/// An iterator over the moves of a GCode file. See [toolpath].
+#[derive(Debug, Clone)]
+pub struct Toolpath<'r> {
+    lines: Lines<'r>,
+    state: MachineState,
+    feature: Option<FeatureRole>,
+}
+
+/// Resolves the G0, G1, G2 and G3 moves of a GCode file into [Segment]s, tracking the modal state
+/// (eg. G90/G91, M82/M83, G92 and units) needed to do so.
+///
+/// Moves are tagged with the feature of the slicer's most recent `;TYPE:` comment.
+///
+/// Moves that do not change the position are skipped, other than arcs that end where they start
+/// (ie. full circles). Invalid lines produce an error but do not stop the iteration.
+pub fn toolpath(input: &str) -> Toolpath<'_> {
+    Toolpath {
+        lines: parse_lines(input),
+        state: MachineState::new(),
+        feature: None,
+    }
+}
+
+impl<'r> Toolpath<'r> {
+    /// The machine state after the most recently returned segment.
+    pub fn state(&self) -> &MachineState {
+        &self.state
+    }
+}
+
+impl<'r> Iterator for Toolpath<'r> {
+    type Item = Result<Segment, GCodeParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+
+            match &line.gcode_line {
+                Some(GCodeLine::DocComment(DocComment::FeatureType(name))) => {
+                    self.feature = Some(FeatureRole::from_type_name(name));
+                }
+                Some(GCodeLine::GCode(gcode)) => {
+                    if let Some(mut segment) = apply_move(&mut self.state, gcode, line.number) {
+                        segment.feature = self.feature.clone();
+                        return Some(Ok(segment));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Applies a GCode to the state, returning the resulting segment if it was a move.
+pub(crate) fn apply_move(
+    state: &mut MachineState,
+    gcode: &GCode,
+    source_line: usize,
+) -> Option<Segment> {
+    let is_move = gcode.mnemonic == G && gcode.minor == 0 && gcode.major <= 3;
+
+    if !is_move {
+        state.apply(gcode);
+        return None;
+    }
+
+    let from = state.machine_position();
+    let arc_plane = state.plane;
+
+    // Arc centers are relative to the start of the move so they are resolved before applying it
+    let arc_center = if gcode.major >= 2 {
+        let to = state.target(gcode);
+        let work_from = state.position;
+
+        arc_center(state, gcode, &work_from, &to)
+            .map(|center| {
+                let mut machine_center = center;
+
+                for (i, axis) in [Axis::X, Axis::Y, Axis::Z].iter().enumerate() {
+                    machine_center[i] += state.work_offset(*axis);
+                }
+
+                machine_center
+            })
+    } else {
+        None
+    };
+
+    state.apply(gcode);
+
+    let to = state.machine_position();
+
+    // Arcs that end where they start are full circles
+    if to == from && arc_center.is_none() {
+        return None;
+    }
+
+    let extrusion = to[Axis::E.index()] - from[Axis::E.index()];
+
+    let kind = match arc_center {
+        Some(center) => SegmentKind::Arc {
+            clockwise: gcode.major == 2,
+            center,
+            plane: arc_plane,
+        },
+        None if extrusion > 0.0 && distance(&from, &to) > 0.0 => SegmentKind::Extrude,
+        None => SegmentKind::Travel,
+    };
+
+    Some(Segment {
+        from,
+        to,
+        feedrate: state.feedrate,
+        extrusion,
+        kind,
+        tool: state.tool,
+        feature: None,
+        source_line,
+    })
+}
@@ -0,0 +1,283 @@
+use super::{
+    rewrite::{rewrite_lines, round_value, with_comments},
+    Axis,
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    MachineState,
+    Mnemonic::*,
+};
+
+/// An affine transformation of X, Y and Z positions in millimetres: a linear transformation
+/// (eg. rotation, scaling or mirroring) followed by a translation.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AffineTransform {
+    /// The linear transformation, in row major order.
+    pub linear: [[f64; 3]; 3],
+    pub translation: [f64; 3],
+}
+
+impl Default for AffineTransform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl AffineTransform {
+    pub fn identity() -> Self {
+        Self {
+            linear: [
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+            translation: [0.0; 3],
+        }
+    }
+
+    pub fn translate(x: f64, y: f64, z: f64) -> Self {
+        Self {
+            translation: [x, y, z],
+            ..Self::identity()
+        }
+    }
+
+    /// A counter-clockwise rotation around the Z axis, as seen from above.
+    pub fn rotate_z(degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+
+        Self {
+            linear: [
+                [cos, -sin, 0.0],
+                [sin, cos, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+            translation: [0.0; 3],
+        }
+    }
+
+    pub fn scale(x: f64, y: f64, z: f64) -> Self {
+        Self {
+            linear: [
+                [x, 0.0, 0.0],
+                [0.0, y, 0.0],
+                [0.0, 0.0, z],
+            ],
+            translation: [0.0; 3],
+        }
+    }
+
+    /// Mirrors X positions (ie. reflects across the plane X = 0).
+    pub fn mirror_x() -> Self {
+        Self::scale(-1.0, 1.0, 1.0)
+    }
+
+    /// Mirrors Y positions (ie. reflects across the plane Y = 0).
+    pub fn mirror_y() -> Self {
+        Self::scale(1.0, -1.0, 1.0)
+    }
+
+    /// Applies this transformation and then `next`.
+    pub fn then(&self, next: &AffineTransform) -> Self {
+        let mut linear = [[0.0; 3]; 3];
+
+        for (row, linear_row) in linear.iter_mut().enumerate() {
+            for (column, value) in linear_row.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|i| next.linear[row][i] * self.linear[i][column])
+                    .sum();
+            }
+        }
+
+        let translated = next.transform_vector(self.translation);
+
+        Self {
+            linear,
+            translation: [
+                translated[0] + next.translation[0],
+                translated[1] + next.translation[1],
+                translated[2] + next.translation[2],
+            ],
+        }
+    }
+
+    /// This transformation relative to a center point (eg. a rotation around the middle of the
+    /// bed rather than the origin).
+    pub fn about(&self, center: [f64; 3]) -> Self {
+        let [x, y, z] = center;
+
+        Self::translate(-x, -y, -z)
+            .then(self)
+            .then(&Self::translate(x, y, z))
+    }
+
+    pub fn transform_point(&self, point: [f64; 3]) -> [f64; 3] {
+        let mut transformed = self.transform_vector(point);
+
+        for (value, translation) in transformed.iter_mut().zip(self.translation.iter()) {
+            *value += translation;
+        }
+
+        transformed
+    }
+
+    /// Transforms a direction or offset (eg. a relative move), which is not translated.
+    pub fn transform_vector(&self, vector: [f64; 3]) -> [f64; 3] {
+        let mut transformed = [0.0; 3];
+
+        for (value, row) in transformed.iter_mut().zip(self.linear.iter()) {
+            *value = row.iter().zip(vector.iter()).map(|(a, b)| a * b).sum();
+        }
+
+        transformed
+    }
+}
+
+const POSITION_AXES: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+const OFFSET_LETTERS: [char; 3] = ['I', 'J', 'K'];
+
+/// Applies an affine transformation to a program, eg. to move a print to a different part of
+/// the bed or to mirror it for duplication mode.
+///
+/// Absolute and relative moves, G2/G3 arc centers (I/J/K, or R for uniform scaling) and G92
+/// positions are transformed, and arcs are reversed when the transformation mirrors their
+/// plane. Extrusion and feedrates are not changed. Comments are kept.
+///
+/// Positions reached by homing (G28) and work coordinate system offsets (G10 L2/L20) are not
+/// transformed. Arcs must stay in their plane (eg. only rotate XY arcs around the Z axis).
+pub fn transform_gcode(
+    input: &str,
+    transform: &AffineTransform,
+) -> Result<String, GCodeParseError> {
+    rewrite_lines(input, |line, state| {
+        let gcode = match &line.gcode_line {
+            Some(GCodeLine::GCode(gcode)) => gcode,
+            _ => return None,
+        };
+
+        match (gcode.mnemonic, gcode.major, gcode.minor) {
+            (General, 0..=3, 0) | (General, 92, 0) => {
+                Some(with_comments(&transform_command(state, gcode, transform), line.text))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// The axes written when transforming a command: the axes it sets, and the axes the
+/// transformation mixes them into (eg. Y for an X move rotated around Z).
+fn written_axes(gcode: &GCode, letters: &[char; 3], transform: &AffineTransform) -> [bool; 3] {
+    let given = letters.map(|letter| gcode.argument(letter).is_some());
+    let mut written = given;
+
+    for (row, written) in written.iter_mut().enumerate() {
+        for (column, given) in given.iter().enumerate() {
+            if *given && transform.linear[row][column] != 0.0 {
+                *written = true;
+            }
+        }
+    }
+
+    written
+}
+
+fn transform_command(state: &MachineState, gcode: &GCode, transform: &AffineTransform) -> GCode<'static> {
+    let is_set_position = gcode.major == 92;
+    let relative = !is_set_position && state.is_relative(Axis::X);
+
+    let position = if relative {
+        let delta = POSITION_AXES.map(|axis| {
+            state.to_millimeters(gcode.value(axis.letter()).unwrap_or(0.0))
+        });
+
+        transform.transform_vector(delta)
+    } else {
+        // G92 sets the given axes of the current position without moving
+        let target = if is_set_position {
+            let mut target = state.position;
+
+            for axis in POSITION_AXES.iter().copied() {
+                if let Some(value) = gcode.value(axis.letter()) {
+                    target[axis.index()] = state.to_millimeters(value);
+                }
+            }
+
+            target
+        } else {
+            state.target(gcode)
+        };
+
+        transform.transform_point([target[0], target[1], target[2]])
+    };
+
+    let position_letters = POSITION_AXES.map(Axis::letter);
+    let written_positions = written_axes(gcode, &position_letters, transform);
+
+    let mut major = gcode.major;
+    // The transformed arc center offset, and the scaling of the arc's radius
+    let mut arc = None;
+
+    if major == 2 || major == 3 {
+        let [a, b, _] = state.plane.axes();
+        let (a, b) = (a.index(), b.index());
+
+        // Mirroring the arc's plane reverses the arc
+        let determinant = transform.linear[a][a] * transform.linear[b][b]
+            - transform.linear[a][b] * transform.linear[b][a];
+
+        if determinant < 0.0 {
+            major = if major == 2 { 3 } else { 2 };
+        }
+
+        let offset = OFFSET_LETTERS.map(|letter| {
+            state.to_millimeters(gcode.value(letter).unwrap_or(0.0))
+        });
+
+        arc = Some((transform.transform_vector(offset), determinant.abs().sqrt()));
+    }
+
+    let mut transformed = GCode::new(gcode.mnemonic, major, gcode.minor);
+    transformed.line_number = gcode.line_number;
+
+    let mut positions_written = false;
+    let mut offsets_written = false;
+
+    for (key, value) in gcode.arguments() {
+        let key = key.to_ascii_uppercase();
+
+        match (key, arc) {
+            (key, _) if position_letters.contains(&key) => {
+                if !positions_written {
+                    for (i, letter) in position_letters.iter().enumerate() {
+                        if written_positions[i] {
+                            let value = round_value(state.units.from_millimeters(position[i]));
+                            transformed.set_argument(*letter, Some(value));
+                        }
+                    }
+
+                    positions_written = true;
+                }
+            }
+            (key, Some((offset, _))) if OFFSET_LETTERS.contains(&key) => {
+                if !offsets_written {
+                    let written_offsets = written_axes(gcode, &OFFSET_LETTERS, transform);
+
+                    for (i, letter) in OFFSET_LETTERS.iter().enumerate() {
+                        if written_offsets[i] {
+                            let value = round_value(state.units.from_millimeters(offset[i]));
+                            transformed.set_argument(*letter, Some(value));
+                        }
+                    }
+
+                    offsets_written = true;
+                }
+            }
+            ('R', Some((_, scale))) => {
+                transformed.set_argument('R', value.map(|radius| round_value(radius * scale)));
+            }
+            (key, _) => transformed.set_argument(key, *value),
+        }
+    }
+
+    transformed
+}
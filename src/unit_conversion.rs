@@ -0,0 +1,85 @@
+use super::{
+    rewrite::{rewrite_lines, round_value, with_comments},
+    GCode,
+    GCodeLine,
+    GCodeParseError,
+    Mnemonic::*,
+    Units,
+};
+
+/// The arguments of G commands that are lengths (or, for F, lengths per minute) and so depend on
+/// the units. Other arguments (eg. S, P, T, H, L) are not lengths.
+const LENGTH_LETTERS: &str = "XYZUVWIJKRQEF";
+
+fn units_command(units: Units) -> GCode<'static> {
+    match units {
+        Units::Inches => GCode::new(General, 20, 0),
+        Units::Millimeters => GCode::new(General, 21, 0),
+    }
+}
+
+/// Converts a program to inches (G20) or millimetres (G21), rewriting the G20/G21 commands and
+/// the lengths of every G command: positions, arc offsets and radii, canned cycle depths,
+/// extrusion and feedrates.
+///
+/// Feedrates in inverse time mode (G93) are not lengths so are left unchanged, as are M commands
+/// and comments.
+/// A units command is added before the first command when converting a program that relies on
+/// the default millimetres to inches.
+pub fn convert_units(input: &str, units: Units) -> Result<String, GCodeParseError> {
+    let mut is_first_gcode = true;
+    let mut inverse_time = false;
+
+    rewrite_lines(input, |line, state| {
+        let gcode = match &line.gcode_line {
+            Some(GCodeLine::GCode(gcode)) => gcode,
+            _ => return None,
+        };
+
+        let is_units_command = gcode.mnemonic == General
+            && gcode.minor == 0
+            && (gcode.major == 20 || gcode.major == 21);
+
+        match (gcode.mnemonic, gcode.major, gcode.minor) {
+            (General, 93, 0) => inverse_time = true,
+            (General, 94, 0) => inverse_time = false,
+            _ => {}
+        }
+
+        let converted = if is_units_command {
+            Some(with_comments(&units_command(units), line.text))
+        } else if gcode.mnemonic == General && state.units != units {
+            let converted = convert_lengths(gcode, state.units, units, inverse_time);
+            Some(with_comments(&converted, line.text))
+        } else {
+            None
+        };
+
+        let needs_units_command = is_first_gcode && !is_units_command && units != Units::Millimeters;
+        is_first_gcode = false;
+
+        if needs_units_command {
+            let line = converted.unwrap_or_else(|| line.text.to_string());
+
+            return Some(format!("{}\n{}", units_command(units), line));
+        }
+
+        converted
+    })
+}
+
+fn convert_lengths<'r>(gcode: &GCode<'r>, from: Units, to: Units, inverse_time: bool) -> GCode<'r> {
+    let mut converted = gcode.clone();
+
+    for (key, value) in gcode.arguments() {
+        let is_length = LENGTH_LETTERS.contains(key.to_ascii_uppercase())
+            && !(inverse_time && key.eq_ignore_ascii_case(&'F'));
+
+        if let (true, Some(value)) = (is_length, value) {
+            let millis = from.to_millimeters(*value);
+            converted.set_argument(*key, Some(round_value(to.from_millimeters(millis))));
+        }
+    }
+
+    converted
+}
@@ -0,0 +1,36 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nom_gcode::parse_gcode;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[test]
+fn parsing_lines_does_not_allocate() {
+    let src = include_str!("data/program_1.gcode");
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+
+    for line in src.lines() {
+        let (_, gcode_line) = parse_gcode(line).unwrap();
+        std::hint::black_box(gcode_line);
+    }
+
+    assert_eq!(ALLOCATIONS.load(Ordering::SeqCst) - before, 0);
+}
@@ -0,0 +1,307 @@
+#![cfg(feature = "std")]
+
+use nom_gcode::*;
+
+fn assert_close(a: f64, b: f64) {
+    assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+}
+
+fn limits() -> MotionLimits {
+    MotionLimits {
+        acceleration: 1000.0,
+        travel_acceleration: 1000.0,
+        ..MotionLimits::default()
+    }
+}
+
+fn seconds(src: &str) -> f64 {
+    estimate_print_time(src, &limits()).unwrap().total.as_secs_f64()
+}
+
+#[test]
+fn trapezoidal_move_time() {
+    // Accelerating to and from 100mm/s takes 0.1s and 5mm each
+    assert_close(seconds("G1 X100 F6000"), 0.1 + 0.1 + 90.0 / 100.0);
+
+    // Straight moves are joined without slowing down
+    assert_close(seconds("G1 X50 F6000\nG1 X100"), 0.1 + 0.1 + 90.0 / 100.0);
+
+    // Short moves never reach their nominal speed
+    assert_close(seconds("G1 X2.5 F6000"), 2.0 * (2.5f64 / 1000.0).sqrt());
+}
+
+#[test]
+fn corners_and_dwells_slow_down() {
+    let straight = seconds("G1 X50 F6000\nG1 X100");
+    let corner = seconds("G1 X50 F6000\nG1 X50 Y50");
+
+    assert!(corner > straight);
+    assert!(corner < seconds("G1 X50 F6000\nG4 P0\nG1 X50 Y50"));
+
+    assert_close(seconds("G1 X100 F6000\nG4 P500\nG4 S1"), 1.1 + 1.5);
+}
+
+#[test]
+fn full_circle_arcs_take_time() {
+    let line = seconds("G1 X10 Y0 F6000");
+    let circle = seconds("G1 X10 Y0 F6000\nG2 X10 Y0 I-10 J0");
+
+    // The 62.8mm circle takes at least 0.628s at 100mm/s
+    assert!(circle - line > 0.628, "{} - {}", circle, line);
+}
+
+#[test]
+fn limits_are_updated_by_the_program() {
+    assert_close(seconds("M203 X50\nG1 X100 F6000"), 0.05 + 0.05 + 97.5 / 50.0);
+    assert_close(seconds("M204 P2000\nG1 X100 F6000 E1"), 0.05 + 0.05 + 95.0 / 100.0);
+}
+
+#[test]
+fn zero_feedrates_and_limits_are_ignored() {
+    assert_close(seconds("G1 F0\nG1 X10 E1"), seconds("G1 X10 E1"));
+    assert_close(seconds("G1 X10 F600\nG1 X20 F0"), seconds("G1 X20 F600"));
+    assert_close(seconds("M204 S0\nG1 X10 F600"), seconds("G1 X10 F600"));
+    assert_close(seconds("M203 X0\nG1 X10 F600"), seconds("G1 X10 F600"));
+    assert_close(seconds("M201 X0\nG1 X10 F600"), seconds("G1 X10 F600"));
+    assert_close(seconds("G4 P-100\nG4 S-1"), 0.0);
+
+    let zero_limits = MotionLimits {
+        acceleration: 0.0,
+        max_feedrate: [0.0; 4],
+        ..MotionLimits::default()
+    };
+    let estimate = estimate_print_time("G1 X10 E1\nG1 X20 E2 F600", &zero_limits).unwrap();
+    assert_close(estimate.total.as_secs_f64(), 1.0);
+
+    let stats = feature_statistics(";TYPE:Skirt\nG1 F0\nG1 X10 E1", &limits()).unwrap();
+    assert_eq!(stats.features.len(), 1);
+}
+
+#[test]
+fn line_and_layer_times() {
+    let src = "G1 Z0.2 F6000\nG1 X100 E1\nG1 Z0.4\nG1 X0 E2";
+
+    let estimate = estimate_print_time(src, &limits()).unwrap();
+
+    assert_eq!(estimate.line_times.len(), 4);
+    assert_eq!(estimate.line_times[3].1, estimate.total);
+    assert_eq!(estimate.layer_times.len(), 2);
+    assert_eq!(estimate.layer_times[1].z, 0.4);
+    assert_close(
+        estimate.layer_times.iter().map(|layer| layer.duration.as_secs_f64()).sum::<f64>()
+            + estimate.line_times[0].1.as_secs_f64(),
+        estimate.total.as_secs_f64(),
+    );
+}
+
+#[test]
+fn filament_usage_from_extrusion_moves() {
+    let src = "\
+M82
+G1 X10 E5
+G1 E3
+G1 X20 E8
+G92 E0
+M83
+G1 X30 E2
+T1
+G1 X40 E4
+G10
+G10
+G11
+";
+
+    let usage = filament_usage(src, &FirmwareRetraction::default()).unwrap();
+
+    let t0 = &usage.extruders[&0];
+    assert_close(t0.extruded, 12.0);
+    assert_close(t0.retracted, 2.0);
+    assert_eq!(t0.retractions, 1);
+    assert_close(t0.length(), 10.0);
+
+    // Firmware retractions are recovered by G11
+    let t1 = &usage.extruders[&1];
+    assert_close(t1.length(), 4.0);
+    assert_eq!(t1.retractions, 1);
+
+    assert_close(usage.length(), 14.0);
+
+    // Swap retractions are recovered with the swap extra length, even when the lengths are equal
+    // or change before the G11
+    let src = "M207 S3 W3\nM208 S0 W1\nG10 S1\nG11\nG10\nM207 S13\nG11\n";
+    let usage = filament_usage(src, &FirmwareRetraction::default()).unwrap();
+    let t0 = &usage.extruders[&0];
+
+    assert_close(t0.retracted, 6.0);
+    assert_close(t0.extruded, 7.0);
+
+    // 1000mm of 1.75mm PLA
+    let meter = ExtruderUsage { extruded: 1000.0, ..ExtruderUsage::default() };
+    assert!((meter.mass(1.75, 1.24) - 2.98).abs() < 0.01);
+}
+
+#[test]
+fn layers_from_slicer_markers() {
+    let src = "\
+G28
+;LAYER_CHANGE
+;Z:0.2
+;HEIGHT:0.2
+G1 Z0.2
+G1 X10 E1
+;LAYER_CHANGE
+;LAYER:1
+;Z:0.4
+;HEIGHT:0.2
+G1 Z0.4
+G1 X0 E2
+M84
+";
+
+    let index = LayerIndex::new(src).unwrap();
+
+    assert_eq!(index.layers.len(), 2);
+
+    let first = &index.layers[0];
+    assert_eq!(first.number, None);
+    assert_close(first.z, 0.2);
+    assert_eq!(first.lines, 2..7);
+    assert_eq!(&src[first.span.clone()], ";LAYER_CHANGE\n;Z:0.2\n;HEIGHT:0.2\nG1 Z0.2\nG1 X10 E1\n");
+
+    let second = index.layer(1).unwrap();
+    assert_close(second.z, 0.4);
+    assert_close(second.thickness, 0.2);
+    assert_eq!(second.lines, 7..14);
+    assert_eq!(second.span.end, src.len());
+
+    assert_eq!(index.layer_containing_line(12), Some(second));
+    assert_eq!(index.layer_containing_line(1), None);
+}
+
+#[test]
+fn layers_from_z_moves() {
+    let src = "\
+G28
+G1 Z0.3
+G1 X10 E1
+G1 Z1
+G1 X20
+G1 Z0.3
+G1 X30 E2
+G1 Z0.6
+G1 X40 E3
+";
+
+    let index = LayerIndex::new(src).unwrap();
+
+    // The Z hop does not start a layer
+    assert_eq!(index.layers.len(), 2);
+
+    assert_eq!(index.layers[0].lines, 2..8);
+    assert_close(index.layers[0].thickness, 0.3);
+
+    let second = index.layer_at_z(0.6).unwrap();
+    assert_eq!(second.number, None);
+    assert_close(second.thickness, 0.3);
+    assert_eq!(&src[second.span.clone()], "G1 Z0.6\nG1 X40 E3\n");
+}
+
+#[test]
+fn bounds_of_extruding_and_travel_moves() {
+    let src = "\
+G1 Z0.2
+G1 X10 Y10
+G1 X20 E1
+T1
+G1 X30 Y5 E2
+G1 X50 Y0
+";
+
+    let bounds = bounds(src).unwrap();
+
+    let extruding = bounds.total.extruding.unwrap();
+    assert_eq!(extruding.min, [10.0, 5.0, 0.2]);
+    assert_eq!(extruding.max, [30.0, 10.0, 0.2]);
+    assert_eq!(bounds.total.all_moves.unwrap().max, [50.0, 10.0, 0.2]);
+
+    let t0 = bounds.tools[&0].extruding.unwrap();
+    assert_eq!(t0.size(), [10.0, 0.0, 0.0]);
+
+    let t1 = &bounds.tools[&1];
+    assert_eq!(t1.extruding.unwrap().min, [20.0, 5.0, 0.2]);
+    assert_eq!(t1.all_moves.unwrap().max, [50.0, 10.0, 0.2]);
+}
+
+#[test]
+fn moves_outside_the_envelope_are_reported() {
+    let src = "\
+G1 Z0.2
+G1 X200 Y100 E1
+G1 X250 Y210
+G1 Z300
+";
+
+    let envelope = MachineEnvelope::new(220.0, 220.0, 250.0);
+    let violations = check_envelope(src, &envelope).unwrap();
+
+    assert_eq!(violations, vec![
+        EnvelopeViolation { source_line: 3, axis: Axis::X, position: 250.0, limit: 220.0 },
+        EnvelopeViolation { source_line: 4, axis: Axis::X, position: 250.0, limit: 220.0 },
+        EnvelopeViolation { source_line: 4, axis: Axis::Z, position: 300.0, limit: 250.0 },
+    ]);
+
+    assert!(check_envelope("G1 X-1", &envelope).unwrap()[0].limit == 0.0);
+
+    // A full circle around the origin
+    let violations = check_envelope("G1 X10 Y0\nG2 X10 Y0 I-10 J0", &envelope).unwrap();
+
+    assert_eq!(violations, vec![
+        EnvelopeViolation { source_line: 2, axis: Axis::X, position: -10.0, limit: 0.0 },
+        EnvelopeViolation { source_line: 2, axis: Axis::Y, position: -10.0, limit: 0.0 },
+    ]);
+}
+
+#[test]
+fn extrusion_and_time_per_feature() {
+    let src = "\
+G1 Z0.2 F6000
+;TYPE:WALL-OUTER
+G1 X100 E5
+;TYPE:FILL
+G4 P500
+G1 X0 E7
+G1 E6
+;TYPE:Something new
+G1 X10 E7
+;TYPE:Another thing
+G1 X20 E8
+";
+
+    let statistics = feature_statistics(src, &limits()).unwrap();
+
+    let walls = &statistics.features[&FeatureRole::ExternalPerimeter];
+    let infill = &statistics.features[&FeatureRole::Infill];
+
+    assert_close(walls.extrusion, 5.0);
+    assert_close(infill.extrusion, 2.0);
+
+    // Unknown feature types are kept apart
+    let something = &statistics.features[&FeatureRole::Other("Something new".to_string())];
+    let another = &statistics.features[&FeatureRole::Other("Another thing".to_string())];
+
+    assert_close(something.extrusion, 1.0);
+    assert_close(another.extrusion, 1.0);
+    // Accelerating to and decelerating from 100mm/s, less a little for the junction with the Z move
+    assert!((walls.duration.as_secs_f64() - 1.1).abs() < 0.01);
+    assert!(infill.duration.as_secs_f64() > 1.1 + 0.5);
+
+    assert_close(statistics.untagged.extrusion, 0.0);
+    assert!(statistics.untagged.duration > std::time::Duration::default());
+
+    let total: f64 = statistics.features.values()
+        .chain(std::iter::once(&statistics.untagged))
+        .map(|stats| stats.duration.as_secs_f64())
+        .sum();
+
+    assert!((total - seconds(src)).abs() < 1e-6);
+}
@@ -0,0 +1,83 @@
+use nom_gcode::*;
+
+fn run(src: &str) -> MachineState {
+    let mut state = MachineState::new();
+
+    for line in parse_lines(src) {
+        if let Some(GCodeLine::GCode(gcode)) = line.unwrap().gcode_line {
+            state.apply(&gcode);
+        }
+    }
+
+    state
+}
+
+#[test]
+fn absolute_and_relative_moves() {
+    let state = run("G90\nG1 X10 Y20 F1200\nG91\nG1 X5 E2\nG1 X5 E2\nG90\nG1 Z0.3");
+
+    assert_eq!(state.position, [20.0, 20.0, 0.3, 4.0]);
+    assert_eq!(state.feedrate, Some(1200.0));
+}
+
+#[test]
+fn relative_extrusion() {
+    let state = run("M83\nG1 X10 E1.5\nG1 X20 E1.5\nM82\nG1 X30 E10");
+
+    assert_eq!(state.position, [30.0, 0.0, 0.0, 10.0]);
+    assert_eq!(state.extruder_positioning, Positioning::Absolute);
+}
+
+#[test]
+fn inches_are_converted_to_millimeters() {
+    let state = run("G20\nG1 X1 F10\nG21\nG1 Y1");
+
+    assert_eq!(state.position, [25.4, 1.0, 0.0, 0.0]);
+    assert_eq!(state.feedrate, Some(254.0));
+}
+
+#[test]
+fn g92_offsets_position_without_moving() {
+    let state = run("G1 X10 E5\nG92 X0 E0\nG1 X5 E1");
+
+    assert_eq!(state.position, [5.0, 0.0, 0.0, 1.0]);
+    assert_eq!(state.machine_position(), [15.0, 0.0, 0.0, 6.0]);
+
+    let state = run("G1 X10\nG92 X0\nG28 X");
+
+    assert_eq!(state.position[0], 0.0);
+    assert_eq!(state.machine_position()[0], 0.0);
+}
+
+#[test]
+fn work_coordinate_systems() {
+    let state = run("G10 L2 P2 X100 Y50\nG55\nG1 X10 Y10\nG54");
+
+    assert_eq!(state.coordinate_system, 0);
+    assert_eq!(state.machine_position(), [110.0, 60.0, 0.0, 0.0]);
+    assert_eq!(state.position, [110.0, 60.0, 0.0, 0.0]);
+
+    let state = run("G59.3");
+
+    assert_eq!(state.coordinate_system, 8);
+}
+
+#[test]
+fn plane_tool_and_temperatures() {
+    let state = run("G18\nM104 S210\nM104 T1 S190\nM140 S60\nT1\nM109 R200");
+
+    assert_eq!(state.plane, Plane::ZX);
+    assert_eq!(state.tool, 1);
+    assert_eq!(state.hotend_temperatures.get(&0), Some(&210.0));
+    assert_eq!(state.hotend_temperatures.get(&1), Some(&200.0));
+    assert_eq!(state.bed_temperature, Some(60.0));
+}
+
+#[test]
+fn fan_speeds() {
+    let state = run("M106 S128\nM106 P1\nM106 P2 S100\nM107 P2");
+
+    assert_eq!(state.fan_speeds.get(&0), Some(&128.0));
+    assert_eq!(state.fan_speeds.get(&1), Some(&255.0));
+    assert_eq!(state.fan_speeds.get(&2), Some(&0.0));
+}
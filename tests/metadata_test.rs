@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use nom_gcode::*;
+
+#[test]
+fn cura_metadata() {
+    let src = "\
+;FLAVOR:Marlin
+;TIME:6666
+;Filament used: 1.5m, 0.25m
+;Layer height: 0.2
+;EXTRUDER_TRAIN.0.INITIAL_TEMPERATURE:200
+;EXTRUDER_TRAIN.0.NOZZLE.DIAMETER:0.4
+;EXTRUDER_TRAIN.1.INITIAL_TEMPERATURE:210
+;EXTRUDER_TRAIN.1.NOZZLE.DIAMETER:0.6
+;BUILD_PLATE.INITIAL_TEMPERATURE:60
+;Generated with Cura_SteamEngine 5.4.0
+G28
+;TYPE:SKIN
+G1 X10 E1
+;SETTING_3 {\"global_quality\": \"[general]\\\\nversion = 4\\\\n\\\\n[values]\\\\nadhesion_t
+;SETTING_3 ype = skirt\\\\n\\\\n\", \"extruder_quality\": [\"[general]\\\\nversion = 4\\\\n\\\\n[
+;SETTING_3 values]\\\\nmaterial_print_temperature = 205\\\\n\\\\n\"]}
+";
+
+    let metadata = slicer_metadata(src);
+
+    assert_eq!(metadata.slicer, Some(Slicer::Cura));
+    assert_eq!(metadata.version.as_deref(), Some("5.4.0"));
+    assert_eq!(metadata.print_time, Some(Duration::from_secs(6666)));
+    assert_eq!(metadata.filament_used, vec![1500.0, 250.0]);
+    assert_eq!(metadata.nozzle_diameters, vec![0.4, 0.6]);
+    assert_eq!(metadata.hotend_temperatures, vec![200.0, 210.0]);
+    assert_eq!(metadata.bed_temperature, Some(60.0));
+
+    assert_eq!(metadata.settings["adhesion_type"], "skirt");
+    assert_eq!(metadata.settings["material_print_temperature"], "205");
+    assert_eq!(metadata.settings["FLAVOR"], "Marlin");
+
+    // Comments after the header are not settings
+    assert!(!metadata.settings.contains_key("TYPE"));
+    assert!(!metadata.settings.contains_key("version"));
+}
+
+#[test]
+fn prusa_slicer_metadata() {
+    let src = "\
+; generated by PrusaSlicer 2.6.0+linux-x64 on 2023-08-01 at 10:00:00 UTC
+
+G28
+G1 X10 E1
+
+; filament used [mm] = 1234.5, 0.0
+; filament used [g] = 3.7
+; estimated printing time (normal mode) = 1d 1h 2m 3s
+
+; prusaslicer_config = begin
+; bed_temperature = 60,65
+; nozzle_diameter = 0.4,0.4
+; temperature = 215,220
+; prusaslicer_config = end
+";
+
+    let metadata = slicer_metadata(src);
+
+    assert_eq!(metadata.slicer, Some(Slicer::PrusaSlicer));
+    assert_eq!(metadata.version.as_deref(), Some("2.6.0+linux-x64"));
+    assert_eq!(metadata.print_time, Some(Duration::from_secs(86400 + 3600 + 123)));
+    assert_eq!(metadata.filament_used, vec![1234.5, 0.0]);
+    assert_eq!(metadata.nozzle_diameters, vec![0.4, 0.4]);
+    assert_eq!(metadata.hotend_temperatures, vec![215.0, 220.0]);
+    assert_eq!(metadata.bed_temperature, Some(60.0));
+    assert_eq!(metadata.settings["filament used [g]"], "3.7");
+}
+
+#[test]
+fn orca_slicer_metadata() {
+    let src = "\
+; HEADER_BLOCK_START
+; generated by OrcaSlicer 1.8.0 on 2023-11-02 at 09:12:00
+; model printing time: 20m 5s; total estimated time: 26m 36s
+; total layer number: 50
+; total filament length [mm] : 2345.67
+; HEADER_BLOCK_END
+
+; CONFIG_BLOCK_START
+; hot_plate_temp = 55
+; nozzle_diameter = 0.4
+; nozzle_temperature = 220
+; CONFIG_BLOCK_END
+G28
+";
+
+    let metadata = slicer_metadata(src);
+
+    assert_eq!(metadata.slicer, Some(Slicer::OrcaSlicer));
+    assert_eq!(metadata.version.as_deref(), Some("1.8.0"));
+    assert_eq!(metadata.print_time, Some(Duration::from_secs(26 * 60 + 36)));
+    assert_eq!(metadata.filament_used, vec![2345.67]);
+    assert_eq!(metadata.nozzle_diameters, vec![0.4]);
+    assert_eq!(metadata.hotend_temperatures, vec![220.0]);
+    assert_eq!(metadata.bed_temperature, Some(55.0));
+    assert_eq!(metadata.settings["total layer number"], "50");
+}
+
+#[test]
+fn simplify3d_metadata() {
+    let src = "\
+; G-Code generated by Simplify3D(R) Version 4.1.2
+; Nov 2, 2023 at 9:12:00 AM
+; Settings Summary
+;   processName,Process1
+;   extruderDiameter,0.4
+;   temperatureName,Extruder 1,Heated Bed
+;   temperatureHeatedBed,0,1
+;   temperatureSetpointTemperatures,215,60
+G28
+G1 X10 E1
+; Build Summary
+;   Build time: 1 hours 2 minutes
+;   Filament length: 3541.9 mm (3.54 m)
+";
+
+    let metadata = slicer_metadata(src);
+
+    assert_eq!(metadata.slicer, Some(Slicer::Simplify3D));
+    assert_eq!(metadata.version.as_deref(), Some("4.1.2"));
+    assert_eq!(metadata.print_time, Some(Duration::from_secs(3600 + 120)));
+    assert_eq!(metadata.filament_used, vec![3541.9]);
+    assert_eq!(metadata.nozzle_diameters, vec![0.4]);
+    assert_eq!(metadata.hotend_temperatures, vec![215.0]);
+    assert_eq!(metadata.bed_temperature, Some(60.0));
+    assert_eq!(metadata.settings["temperatureName"], "Extruder 1,Heated Bed");
+}
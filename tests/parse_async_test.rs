@@ -0,0 +1,36 @@
+#![cfg(feature = "tokio")]
+
+use nom_gcode::*;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn lines_are_parsed_as_they_are_received() {
+    let (mut writer, reader) = tokio::io::duplex(64);
+
+    let upload = tokio::spawn(async move {
+        for chunk in ["G28\nG1 X1", "0 Y5\n; done", "\nG1 X\u{1}"] {
+            writer.write_all(chunk.as_bytes()).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+    });
+
+    let lines: Vec<_> = gcode_line_stream(BufReader::new(reader)).collect().await;
+    upload.await.unwrap();
+
+    assert_eq!(lines.len(), 4);
+
+    let line = lines[1].as_ref().unwrap();
+    assert_eq!(line.number, 2);
+    assert_eq!(line.text(), "G1 X10 Y5");
+
+    match line.gcode_line() {
+        Some(GCodeLine::GCode(gcode)) => assert_eq!(
+            gcode.arguments().collect::<Vec<_>>(),
+            vec![&('X', Some(10.0)), &('Y', Some(5.0))],
+        ),
+        other => panic!("Expected a GCode, got: {:?}", other),
+    }
+
+    assert!(lines[3].is_err());
+}
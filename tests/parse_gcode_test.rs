@@ -0,0 +1,246 @@
+use nom_gcode::*;
+
+fn parse_single_gcode(src: &str) -> nom_gcode::GCode<'_> {
+    match parse_gcode(src).unwrap() {
+        (_, Some(GCodeLine::GCode(gcode))) => gcode,
+        other => panic!("Expected a GCode, got: {:?}", other),
+    }
+}
+
+#[test]
+fn arguments_keep_full_precision() {
+    let gcode = parse_single_gcode("G1 X123456.789 Y-0.000123 E3.64358");
+
+    let args: Vec<_> = gcode.arguments().cloned().collect();
+
+    assert_eq!(args, vec![
+        ('X', Some(123456.789)),
+        ('Y', Some(-0.000123)),
+        ('E', Some(3.64358)),
+    ]);
+}
+
+#[test]
+fn display_reproduces_argument_digits() {
+    let gcode = parse_single_gcode("G1 X132.273 Y137.397 E3.64358");
+
+    assert_eq!(gcode.to_string(), "G1 X132.273 Y137.397 E3.64358");
+}
+
+#[test]
+fn display_omits_zero_minor_versions() {
+    assert_eq!(parse_single_gcode("G1.0 X1").to_string(), "G1 X1");
+    assert_eq!(parse_single_gcode("G92.1").to_string(), "G92.1");
+}
+
+#[test]
+fn display_includes_line_numbers() {
+    let gcode = parse_single_gcode("N10 G1 X1");
+
+    assert_eq!(gcode.line_number, Some(10));
+    assert_eq!(gcode.to_string(), "N10 G1 X1");
+}
+
+#[test]
+fn byte_order_mark_is_skipped() {
+    let gcode = parse_single_gcode("\u{feff}G28");
+
+    assert_eq!(gcode.major, 28);
+}
+
+#[test]
+fn layer_markers_are_doc_comments() {
+    let doc = |src| match parse_gcode(src).unwrap() {
+        (_, Some(GCodeLine::DocComment(doc))) => doc,
+        other => panic!("Expected a DocComment, got: {:?}", other),
+    };
+
+    assert_eq!(doc(";LAYER:-2\n"), DocComment::Layer(-2));
+    assert_eq!(doc(";LAYER_CHANGE\n"), DocComment::LayerChange);
+    assert_eq!(doc(";Z:0.6"), DocComment::LayerZ { millis: 0.6 });
+    assert_eq!(doc(";HEIGHT:0.2"), DocComment::LayerThickness { millis: 0.2 });
+    assert_eq!(doc(";TYPE:External perimeter\n"), DocComment::FeatureType("External perimeter"));
+
+    // Similar comments are left alone
+    assert_eq!(
+        parse_gcode(";LAYER_COUNT:20").unwrap().1,
+        Some(GCodeLine::Comment(Comment("LAYER_COUNT:20"))),
+    );
+    assert_eq!(
+        parse_gcode(";LAYER_CHANGES").unwrap().1,
+        Some(GCodeLine::Comment(Comment("LAYER_CHANGES"))),
+    );
+}
+
+#[test]
+fn extended_commands() {
+    let command = |src| match parse_gcode(src).unwrap() {
+        (_, Some(GCodeLine::ExtendedCommand(command))) => command,
+        other => panic!("Expected an ExtendedCommand, got: {:?}", other),
+    };
+
+    let pressure_advance = command("SET_PRESSURE_ADVANCE ADVANCE=0.04 EXTRUDER=extruder ; PA\n");
+
+    assert_eq!(pressure_advance.name, "SET_PRESSURE_ADVANCE");
+    assert_eq!(pressure_advance.argument("advance"), Some("0.04"));
+    assert_eq!(pressure_advance.argument("SMOOTH_TIME"), None);
+    assert_eq!(pressure_advance.comment, Some(Comment(" PA")));
+    assert_eq!(
+        pressure_advance.to_string(),
+        "SET_PRESSURE_ADVANCE ADVANCE=0.04 EXTRUDER=extruder",
+    );
+
+    assert_eq!(command("PRINT_START").to_string(), "PRINT_START");
+    assert_eq!(command("PAUSE").name, "PAUSE");
+
+    // Malformed GCode is not mistaken for extended commands
+    for src in ["X10 Y5", "GX10", "Hello world", "PRINTSTART", "print_start", "!!bad"] {
+        assert!(
+            matches!(parse_gcode(src), Err(GCodeParseError::InvalidGCode(_))),
+            "{}: {:?}",
+            src,
+            parse_gcode(src),
+        );
+    }
+}
+
+#[test]
+fn bytes_with_latin_1_comment() {
+    let src = b"G1 X10 ; c\xf4t\xe9\nG1 X20";
+
+    let (remainder, gcode_line) = parse_gcode_bytes(src).unwrap();
+
+    assert_eq!(remainder, b"\nG1 X20");
+
+    let gcode = match gcode_line {
+        Some(GCodeLine::GCode(gcode)) => gcode,
+        other => panic!("Expected a GCode, got: {:?}", other),
+    };
+
+    assert_eq!(gcode.arguments().collect::<Vec<_>>(), vec![&('X', Some(10.0))]);
+}
+
+#[test]
+fn bytes_with_latin_1_comment_line() {
+    let (_, gcode_line) = parse_gcode_bytes(b"\xef\xbb\xbf;Pi\xe8ce").unwrap();
+
+    assert_eq!(gcode_line, Some(GCodeLine::RawComment(RawComment(b"Pi\xe8ce"))));
+}
+
+#[test]
+fn bytes_with_latin_1_parentheses_comment() {
+    let (remainder, gcode_line) = parse_gcode_bytes(b"G1 X1 (caf\xe9) Y2 ; ok\nG28").unwrap();
+
+    assert_eq!(remainder, b"\nG28");
+
+    let gcode = match gcode_line {
+        Some(GCodeLine::GCode(gcode)) => gcode,
+        other => panic!("Expected a GCode, got: {:?}", other),
+    };
+
+    assert_eq!(
+        gcode.arguments().collect::<Vec<_>>(),
+        vec![&('X', Some(1.0)), &('Y', Some(2.0))],
+    );
+
+    let (_, gcode_line) = parse_gcode_bytes(b"(caf\xe9)").unwrap();
+
+    assert_eq!(gcode_line, Some(GCodeLine::RawComment(RawComment(b"caf\xe9"))));
+}
+
+#[test]
+fn bytes_with_invalid_command() {
+    assert!(parse_gcode_bytes(b"G1 X\xe9").is_err());
+}
+
+#[test]
+fn errors_are_displayed() {
+    let err = GCodeParseError::InvalidArguments("X?".to_string());
+
+    assert_eq!(err.to_string(), "Badly formatted GCode arguments. Got: X?");
+}
+
+#[test]
+fn comments_after_arguments_may_follow_spaces() {
+    for src in [
+        "G54 X-75 S500 M3  (Position 6)",
+        "G1 X10 ; move",
+        "G1 X10 (a)  ; b",
+        "G1 X10 (a)  ",
+    ] {
+        let (remainder, gcode_line) = parse_gcode(src).unwrap();
+
+        assert_eq!(remainder, "", "{}", src);
+        assert!(matches!(gcode_line, Some(GCodeLine::GCode(_))), "{}", src);
+    }
+}
+
+#[test]
+fn lines_have_numbers_and_spans() {
+    let src = "G28\r\n\n; comment\nG1 X10";
+
+    let lines: Vec<_> = parse_lines(src).collect::<Result<_, _>>().unwrap();
+
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[1].gcode_line, None);
+    assert_eq!(lines[2].number, 3);
+    assert_eq!(&src[lines[3].span.clone()], "G1 X10");
+    assert_eq!(lines[0].text, "G28");
+}
+
+#[test]
+#[cfg(feature = "rayon")]
+fn parallel_lines_match_serial_lines() {
+    let src = include_str!("data/program_2.gcode").repeat(500);
+
+    let serial: Vec<_> = parse_lines(&src).map(Result::unwrap).collect();
+    let parallel: Vec<_> = par_parse_lines(&src).into_iter().map(Result::unwrap).collect();
+
+    assert_eq!(serial, parallel);
+}
+
+#[test]
+fn streaming_distinguishes_incomplete_from_invalid_lines() {
+    assert!(matches!(
+        parse_gcode_streaming("G1 X10 Y"),
+        Err(nom::Err::Incomplete(_)),
+    ));
+    assert!(matches!(
+        parse_gcode_streaming("G1 X10\r"),
+        Err(nom::Err::Incomplete(_)),
+    ));
+    assert!(matches!(
+        parse_gcode_streaming("G1 X10 Y\u{1}\n"),
+        Err(nom::Err::Error(_)),
+    ));
+
+    let (remainder, gcode_line) = parse_gcode_streaming("G1 X10\r\nG1").unwrap();
+
+    assert_eq!(remainder, "G1");
+    assert!(matches!(gcode_line, Some(GCodeLine::GCode(_))));
+}
+
+#[test]
+fn streaming_recovers_after_invalid_lines() {
+    let mut input = "!!bad\nG1 X1\r\n\rbad\nG28\n";
+    let mut gcodes = vec![];
+    let mut errors = 0;
+
+    while !input.is_empty() {
+        input = match parse_gcode_streaming(input) {
+            Ok((remainder, Some(GCodeLine::GCode(gcode)))) => {
+                gcodes.push(gcode.major);
+                remainder
+            }
+            Ok((remainder, _)) => remainder,
+            Err(nom::Err::Error((remainder, _))) => {
+                errors += 1;
+                remainder
+            }
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        };
+    }
+
+    assert_eq!(gcodes, vec![1, 28]);
+    assert_eq!(errors, 2);
+}
@@ -6,11 +6,11 @@ use nom_gcode::{
 
 pub fn exec_smoke_test(src: &str) {
     src.lines().enumerate().for_each(|(i, line)| {
-        let (remainder, _) = parse_gcode(&line)
-            .expect(&format!("Failed to parse line #{}: {:?}\n\n", i + 1, line));
+        let (remainder, _) = parse_gcode(line)
+            .unwrap_or_else(|_| panic!("Failed to parse line #{}: {:?}\n\n", i + 1, line));
 
         assert!(
-            remainder.len() == 0,
+            remainder.is_empty(),
             "Failed to parse entire line. Line #{}, input: {} output: {}",
             i,
             line,
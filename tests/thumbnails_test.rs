@@ -0,0 +1,94 @@
+use nom_gcode::*;
+
+fn thumbnail(format: ThumbnailFormat, size: u32, bytes: &[u8]) -> Thumbnail {
+    Thumbnail {
+        width: size,
+        height: size,
+        format,
+        bytes: bytes.to_vec(),
+    }
+}
+
+#[test]
+fn thumbnails_are_extracted() {
+    let src = "\
+; generated by PrusaSlicer 2.6.0+linux-x64 on 2023-08-01 at 10:00:00 UTC
+
+;
+; thumbnail begin 16x16 12
+; iVBORw0K
+; Ggo=
+; thumbnail end
+;
+
+; thumbnail_JPG begin 32x24 4
+; /9j/
+; thumbnail_JPG end
+G28
+";
+
+    let thumbnails = thumbnails(src).unwrap();
+
+    assert_eq!(thumbnails, vec![
+        thumbnail(ThumbnailFormat::Png, 16, b"\x89PNG\r\n\x1a\n"),
+        Thumbnail {
+            width: 32,
+            height: 24,
+            format: ThumbnailFormat::Jpg,
+            bytes: vec![0xff, 0xd8, 0xff],
+        },
+    ]);
+}
+
+#[test]
+fn invalid_thumbnails_are_errors() {
+    assert!(thumbnails("; thumbnail begin 16x16 4\n; iVBO").is_err());
+    assert!(thumbnails("; thumbnail begin 16x16 4\n; iV*O\n; thumbnail end").is_err());
+}
+
+#[test]
+fn thumbnails_round_trip() {
+    // Every length of trailing partial chunk, and enough data to span several lines
+    let bytes: Vec<u8> = (0..=255).collect();
+
+    for len in [0, 1, 2, 3, 100, 256] {
+        let original = thumbnail(ThumbnailFormat::Qoi, 8, &bytes[..len]);
+        let gcode = original.to_gcode();
+
+        assert!(gcode.lines().all(|line| line.len() <= 80));
+        assert_eq!(thumbnails(&gcode).unwrap(), vec![original]);
+    }
+}
+
+#[test]
+fn inserted_thumbnails_replace_matching_thumbnails() {
+    let src = "\
+; thumbnail begin 16x16 4
+; AAAA
+; thumbnail end
+; thumbnail begin 32x32 4
+; AAAA
+; thumbnail end\r
+G28\r
+";
+
+    let new_thumbnail = thumbnail(ThumbnailFormat::Png, 16, b"new");
+    let output = insert_thumbnails(src, std::slice::from_ref(&new_thumbnail)).unwrap();
+
+    assert!(output.starts_with("; thumbnail begin 16x16 4\r\n; bmV3\r\n; thumbnail end\r\n"));
+    assert!(output.ends_with("G28\r\n"));
+
+    assert_eq!(thumbnails(&output).unwrap(), vec![
+        new_thumbnail,
+        thumbnail(ThumbnailFormat::Png, 32, &[0, 0, 0]),
+    ]);
+}
+
+#[test]
+fn inserting_into_unterminated_thumbnails_fails() {
+    let src = "; thumbnail begin 16x16 4\n; AQID\nG28\nG1 X10\n";
+    let new_thumbnail = thumbnail(ThumbnailFormat::Png, 16, b"new");
+
+    assert!(insert_thumbnails(src, std::slice::from_ref(&new_thumbnail)).is_err());
+    assert!(insert_thumbnails(src, &[]).is_err());
+}
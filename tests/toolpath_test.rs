@@ -0,0 +1,104 @@
+#![cfg(feature = "std")]
+
+use nom_gcode::*;
+
+fn segments(src: &str) -> Vec<Segment> {
+    toolpath(src).collect::<Result<_, _>>().unwrap()
+}
+
+#[test]
+fn moves_are_resolved_to_absolute_positions() {
+    let segments = segments("G28\nG1 Z0.2 F600\nM83\nG1 X10 E1\nG91\nG1 X5 Y5\nG1 E-1\nG90\nG92 E0\nG1 X0 E2");
+
+    assert_eq!(segments.len(), 5);
+
+    assert_eq!(segments[0].to, [0.0, 0.0, 0.2, 0.0]);
+    assert_eq!(segments[0].kind, SegmentKind::Travel);
+    assert_eq!(segments[0].feedrate, Some(600.0));
+    assert_eq!(segments[0].source_line, 2);
+
+    assert_eq!(segments[1].to, [10.0, 0.0, 0.2, 1.0]);
+    assert_eq!(segments[1].kind, SegmentKind::Extrude);
+    assert_eq!(segments[1].extrusion, 1.0);
+
+    assert_eq!(segments[2].to, [15.0, 5.0, 0.2, 1.0]);
+
+    assert_eq!(segments[3].extrusion, -1.0);
+    assert_eq!(segments[3].kind, SegmentKind::Travel);
+
+    // G92 E0 resets the logical extruder position but not the machine position
+    assert_eq!(segments[4].from, [15.0, 5.0, 0.2, 0.0]);
+    assert_eq!(segments[4].to, [0.0, 5.0, 0.2, 2.0]);
+    assert_eq!(segments[4].extrusion, 2.0);
+}
+
+#[test]
+fn arcs_with_offsets_and_radius() {
+    let segments = segments("G1 X10 Y0\nG3 X0 Y10 I-10 J0\nG2 X10 Y0 R10");
+
+    assert_eq!(segments[1].kind, SegmentKind::Arc {
+        clockwise: false,
+        center: [0.0, 0.0, 0.0],
+        plane: Plane::XY,
+    });
+    assert!((segments[1].length() - 10.0 * std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+
+    match segments[2].kind {
+        SegmentKind::Arc { clockwise: true, center, .. } => {
+            assert!(center[0].abs() < 1e-9);
+            assert!(center[1].abs() < 1e-9);
+        }
+        kind => panic!("Expected a clockwise arc, got {:?}", kind),
+    }
+}
+
+#[test]
+fn full_circle_arcs_are_segments() {
+    let segments = segments("G1 X10 Y0\nG2 X10 Y0 I-10 J0\nG1 X10 Y0");
+
+    assert_eq!(segments.len(), 2);
+    assert!((segments[1].length() - 20.0 * std::f64::consts::PI).abs() < 1e-9);
+}
+
+#[test]
+fn program_2_toolpath() {
+    let segments = segments(include_str!("data/program_2.gcode"));
+
+    assert_eq!(segments.len(), 7);
+    assert_eq!(segments[0].source_line, 6);
+    assert!(matches!(segments[2].kind, SegmentKind::Arc { clockwise: true, .. }));
+    assert!(matches!(segments[4].kind, SegmentKind::Arc { clockwise: false, .. }));
+}
+
+#[test]
+fn arc_bounding_box_includes_bulge() {
+    // A counter-clockwise half circle from (10, 0) to (-10, 0) around the origin
+    let segments = segments("G1 X10\nG3 X-10 Y0 I-10 J0");
+    let bounds = segments[1].bounding_box();
+
+    assert_eq!(bounds.min[0], -10.0);
+    assert_eq!(bounds.max[0], 10.0);
+    assert!(bounds.min[1].abs() < 1e-9);
+    assert!((bounds.max[1] - 10.0).abs() < 1e-9);
+
+    // The clockwise half circle bulges the other way
+    let segments = self::segments("G1 X10\nG2 X-10 Y0 I-10 J0");
+    let bounds = segments[1].bounding_box();
+
+    assert!((bounds.min[1] + 10.0).abs() < 1e-9);
+    assert!(bounds.max[1].abs() < 1e-9);
+}
+
+#[test]
+fn moves_are_tagged_with_features() {
+    let segments = segments("G1 Z0.2\n;TYPE:WALL-OUTER\nG1 X10 E1\n;TYPE:Solid infill\nG1 Y10 E2\n;TYPE:Something new\nG1 X0");
+
+    let features: Vec<_> = segments.into_iter().map(|segment| segment.feature).collect();
+
+    assert_eq!(features, vec![
+        None,
+        Some(FeatureRole::ExternalPerimeter),
+        Some(FeatureRole::SolidInfill),
+        Some(FeatureRole::Other("Something new".to_string())),
+    ]);
+}
@@ -0,0 +1,724 @@
+#![cfg(feature = "std")]
+
+use nom_gcode::*;
+
+fn segments(src: &str) -> Vec<Segment> {
+    toolpath(src).collect::<Result<_, _>>().unwrap()
+}
+
+fn assert_close(a: f64, b: f64) {
+    assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+}
+
+#[test]
+fn linearize_quarter_circle() {
+    let src = "G1 X10 Y0 E0\nG3 X0 Y10 I-10 J0 E5 F600\nG1 X0 Y20";
+
+    let output = linearize_arcs(src, &ArcLinearization::default()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+
+    // The 0.01mm chord tolerance needs 18 segments (more than the 16 needed for 1mm segments)
+    assert_eq!(lines.len(), 20);
+    assert_eq!(lines[0], "G1 X10 Y0 E0");
+    assert!(lines[1].starts_with("G1 X9.9") && lines[1].ends_with(" F600"));
+    assert_eq!(lines[18], "G1 X0 Y10 E5");
+    assert_eq!(lines[19], "G1 X0 Y20");
+
+    for segment in segments(&output).iter().skip(1).take(18) {
+        let radius = segment.to[0].hypot(segment.to[1]);
+        assert_close(radius, 10.0);
+        assert_close(segment.extrusion, 5.0 / 18.0);
+    }
+}
+
+#[test]
+fn linearize_relative_helical_arc_in_zx_plane() {
+    let src = "G18\nG91\nM83\nG2 X10 Z10 Y2 I5 K5 E4";
+
+    let output = linearize_arcs(src, &ArcLinearization {
+        chord_tolerance: 0.5,
+        max_segment_length: 100.0,
+    }).unwrap();
+
+    let segments = segments(&output);
+    let end = segments.last().unwrap().to;
+
+    assert!(segments.len() > 2);
+    assert_close(end[0], 10.0);
+    assert_close(end[1], 2.0);
+    assert_close(end[2], 10.0);
+    assert_close(segments.iter().map(|s| s.extrusion).sum(), 4.0);
+
+    // Points stay on the circle around X5 Z5
+    for segment in &segments {
+        assert_close((segment.to[0] - 5.0).hypot(segment.to[2] - 5.0), 50f64.sqrt());
+    }
+}
+
+#[test]
+fn linearize_arcs_without_centers_and_huge_arcs() {
+    let options = ArcLinearization::default();
+
+    assert_eq!(linearize_arcs("G2 X10 Y0 ; no center", &options).unwrap(), "G1 X10 ; no center\n");
+
+    let output = linearize_arcs("G2 X0 Y0 I1000000", &ArcLinearization {
+        chord_tolerance: 1e-9,
+        max_segment_length: 1e-3,
+    }).unwrap();
+    assert_eq!(output.lines().count(), MAX_ARC_SEGMENTS);
+
+    for chord_tolerance in [0.0, -1.0, f64::NAN] {
+        let options = ArcLinearization { chord_tolerance, ..ArcLinearization::default() };
+
+        assert!(matches!(
+            linearize_arcs("G2 X10 I5", &options),
+            Err(GCodeParseError::InvalidOptions(_)),
+        ));
+    }
+
+    let options = ArcLinearization { max_segment_length: 0.0, ..ArcLinearization::default() };
+    assert!(linearize_arcs("G2 X10 I5", &options).is_err());
+}
+
+#[test]
+fn linearize_radius_arcs() {
+    let output = linearize_arcs("G2 X10 Y10 R-10", &ArcLinearization::default()).unwrap();
+
+    // The long way around (270 degrees) a circle centered at X10 Y0
+    let segments = segments(&output);
+    let length: f64 = segments.iter().map(Segment::length).sum();
+
+    assert!((length / 10.0 - 1.5 * std::f64::consts::PI).abs() < 0.01);
+}
+
+#[test]
+fn fit_arcs_to_linearized_arc() {
+    let src = "G1 X10 Y0 E0\nG3 X0 Y10 I-10 J0 E5 F600\nG1 X0 Y20";
+    let linearized = linearize_arcs(src, &ArcLinearization::default()).unwrap();
+
+    let output = fit_arcs(&linearized, &ArcFitting::default()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("G3 X0 Y10 E5 I-"), "{}", lines[1]);
+    assert!(lines[1].ends_with(" F600"));
+    assert_eq!(lines[2], "G1 X0 Y20");
+
+    let arc = &segments(&output)[1];
+    assert!(matches!(arc.kind, SegmentKind::Arc { clockwise: false, .. }));
+    assert_close(arc.length(), 10.0 * std::f64::consts::FRAC_PI_2);
+}
+
+#[test]
+fn fit_arcs_preserves_relative_extrusion() {
+    let src = "M83\nG1 X10 Y0\nG2 X0 Y-10 I-10 J0 E3";
+    let linearized = linearize_arcs(src, &ArcLinearization::default()).unwrap();
+
+    let output = fit_arcs(&linearized, &ArcFitting::default()).unwrap();
+
+    assert_eq!(output.lines().count(), 3);
+    assert!(output.lines().nth(2).unwrap().starts_with("G2 X0 Y-10 E3"));
+}
+
+#[test]
+fn fit_arcs_leaves_lines_and_travel_moves() {
+    let src = "G1 X10 Y0 E1\nG1 X20 Y0 E2\nG1 X30 Y0 E3\nG1 X40 Y0 E4\nG0 X40 Y10\nG0 X30 Y17\nG0 X20 Y20";
+
+    assert_eq!(fit_arcs(src, &ArcFitting::default()).unwrap().trim_end(), src);
+}
+
+#[test]
+fn translate_absolute_relative_and_g92() {
+    let src = "G1 X10 Y10 E1 F600\nG91\nG1 X5\nG90\nG92 X0\nG1 X1";
+
+    let output = transform_gcode(src, &AffineTransform::translate(100.0, 50.0, 0.0)).unwrap();
+
+    assert_eq!(output, "G1 X110 Y60 E1 F600\nG91\nG1 X5\nG90\nG92 X100\nG1 X101\n");
+}
+
+#[test]
+fn rewritten_lines_keep_comments() {
+    let translate = AffineTransform::translate(5.0, 0.0, 0.0);
+
+    assert_eq!(
+        transform_gcode("G1 X10 Y0 ; perimeter\nG1 X1 (part; 1) Y1 ;a (b)\n", &translate).unwrap(),
+        "G1 X15 Y0 ; perimeter\nG1 X6 Y1 (part; 1) ;a (b)\n",
+    );
+    assert_eq!(
+        convert_units("G1 X25.4 ; one inch", Units::Inches).unwrap(),
+        "G20\nG1 X1 ; one inch\n",
+    );
+    assert_eq!(
+        convert_positioning("G91 ; relative\nG1 X1 ; move", Positioning::Absolute, Positioning::Absolute)
+            .unwrap(),
+        "G90 ; relative\nG1 X1 ; move\n",
+    );
+
+    // Linearized arcs keep their comments on the first line segment
+    let linearized = linearize_arcs("G1 X10\nG2 X-10 I-10 ; arc", &ArcLinearization::default()).unwrap();
+    let commented: Vec<_> = linearized.lines().filter(|line| line.ends_with(" ; arc")).collect();
+
+    assert_eq!(commented, vec![linearized.lines().nth(1).unwrap()]);
+}
+
+#[test]
+fn rotation_writes_mixed_axes() {
+    let src = "G1 X10 F600\nG91\nG1 X5";
+
+    let output = transform_gcode(src, &AffineTransform::rotate_z(90.0)).unwrap();
+
+    assert_eq!(output, "G1 X0 Y10 F600\nG91\nG1 X0 Y5\n");
+}
+
+#[test]
+fn mirroring_reverses_arcs() {
+    let src = "G1 X10 Y0\nG3 X0 Y10 I-10 J0 E1\nG2 X10 Y0 R10";
+
+    let output = transform_gcode(src, &AffineTransform::mirror_x()).unwrap();
+
+    assert_eq!(output, "G1 X-10 Y0\nG2 X0 Y10 I10 J0 E1\nG3 X-10 Y0 R10\n");
+}
+
+#[test]
+fn transformed_toolpath_matches_transformed_segments() {
+    let src = "\
+G28
+G1 X10 Y20 Z0.2 F1200
+G1 X30 Y20 E1
+G3 X30 Y40 I0 J10 E2
+G91
+G1 X-5 Y-5 E0.5
+G90
+G92 X0 Y0 E0
+G2 X10 Y0 R5 E1
+G1 X20 Y5 E2
+";
+
+    let transform = AffineTransform::rotate_z(30.0)
+        .then(&AffineTransform::mirror_y())
+        .then(&AffineTransform::scale(2.0, 2.0, 1.0))
+        .about([100.0, 100.0, 0.0]);
+
+    let output = transform_gcode(src, &transform).unwrap();
+
+    let original = segments(src);
+    let transformed = segments(&output);
+
+    assert_eq!(original.len(), transformed.len());
+
+    // The first move starts from the homed position, which is not transformed
+    for (original, transformed) in original.iter().zip(transformed.iter()).skip(1) {
+        for (point, transformed_point) in [(original.from, transformed.from), (original.to, transformed.to)] {
+            let expected = transform.transform_point([point[0], point[1], point[2]]);
+
+            for i in 0..3 {
+                assert_close(transformed_point[i], expected[i]);
+            }
+        }
+
+        assert_close(transformed.extrusion, original.extrusion);
+        assert_close(transformed.length(), original.length() * 2.0);
+    }
+}
+
+#[test]
+fn convert_inches_to_millimeters() {
+    let src = "\
+%
+G20 (inches)
+G0 X1 Y-0.5 Z0.1 F20
+G83 X2 Y2 Z-0.5 R0.1 Q0.125 F5
+G4 P0.5
+M3 S1200
+T2 M6
+G93
+G1 X3 F2
+G94
+G2 X4 Y3 I1 J0 F10
+%
+";
+
+    let output = convert_units(src, Units::Millimeters).unwrap();
+
+    assert_eq!(output, "\
+%
+G21 (inches)
+G0 X25.4 Y-12.7 Z2.54 F508
+G83 X50.8 Y50.8 Z-12.7 R2.54 Q3.175 F127
+G4 P0.5
+M3 S1200
+T2 M6
+G93
+G1 X76.2 F2
+G94
+G2 X101.6 Y76.2 I25.4 J0 F254
+%
+");
+}
+
+#[test]
+fn convert_millimeters_to_inches() {
+    let src = "G28\nG1 X25.4 Y12.7 E2.54 F1524\nG92 E0";
+
+    let output = convert_units(src, Units::Inches).unwrap();
+
+    assert_eq!(output, "G20\nG28\nG1 X1 Y0.5 E0.1 F60\nG92 E0\n");
+
+    // Converting back only rewrites the units command
+    assert_eq!(
+        convert_units(&output, Units::Millimeters).unwrap(),
+        "G21\nG28\nG1 X25.4 Y12.7 E2.54 F1524\nG92 E0\n",
+    );
+}
+
+#[test]
+fn relative_to_absolute_resolves_g92() {
+    let src = "\
+G28
+G91
+M83
+G1 Z0.2
+G1 X10 Y10 E1
+G1 X5 E0.5
+G90
+M82
+G92 E0
+G1 X20 E1
+";
+
+    let output = convert_positioning(src, Positioning::Absolute, Positioning::Absolute).unwrap();
+
+    assert_eq!(output, "\
+G28
+G90
+M82
+G1 Z0.2
+G1 X10 Y10 E1
+G1 X15 E1.5
+G90
+M82
+G1 X20 E2.5
+");
+}
+
+#[test]
+fn absolute_to_relative() {
+    let src = "G28\nG1 Z0.2 F600\nG1 X10 Y10 E1\nG92 E0\nG1 X5 E0.5\nG2 X0 Y15 I-5 J0 E1";
+
+    let output = convert_positioning(src, Positioning::Relative, Positioning::Relative).unwrap();
+
+    assert_eq!(output, "\
+G91
+M83
+G28
+G1 Z0.2 F600
+G1 X10 Y10 E1
+G1 X-5 E0.5
+G2 X-5 Y5 I-5 J0 E0.5
+");
+
+    // Modes set before the first move are rewritten rather than added
+    assert_eq!(
+        convert_positioning("G90\nM82\nG1 X1 E1", Positioning::Relative, Positioning::Relative).unwrap(),
+        "G91\nM83\nG1 X1 E1\n",
+    );
+    assert_eq!(
+        convert_positioning("G28\nM82\nG1 X1 E1", Positioning::Relative, Positioning::Relative).unwrap(),
+        "G91\nG28\nM83\nG1 X1 E1\n",
+    );
+
+    // Both programs follow the same path
+    let original = segments(src);
+    let converted = segments(&output);
+
+    assert_eq!(original.len(), converted.len());
+
+    for (original, converted) in original.iter().zip(converted.iter()) {
+        assert_eq!(original.to[..3], converted.to[..3]);
+        assert_close(original.extrusion, converted.extrusion);
+    }
+}
+
+#[test]
+fn insert_filament_change_at_layers() {
+    let src = "\
+G28\r
+;LAYER:0\r
+G1 Z0.2\r
+G1 X10 E1\r
+;LAYER:1\r
+G1 Z0.4\r
+G1 X0 E2\r
+;LAYER:2\r
+G1 Z0.6\r
+G1 X10 E3\r
+";
+
+    let output = insert_at_layers(src, &[
+        LayerInsertion::new(LayerSelector::Z(0.5), "M117 Changing filament\nM600"),
+        LayerInsertion::new(LayerSelector::Number(1), "M0"),
+        LayerInsertion::new(LayerSelector::Index(2), "M104 S210"),
+        LayerInsertion::new(LayerSelector::Number(7), "M0"),
+    ]).unwrap();
+
+    assert_eq!(output, "\
+G28\r
+;LAYER:0\r
+G1 Z0.2\r
+G1 X10 E1\r
+M0\r
+;LAYER:1\r
+G1 Z0.4\r
+G1 X0 E2\r
+M117 Changing filament\r
+M600\r
+M104 S210\r
+;LAYER:2\r
+G1 Z0.6\r
+G1 X10 E3\r
+");
+}
+
+#[test]
+fn insert_at_z_without_markers() {
+    let src = "G28\nG1 Z0.3\nG1 X10 E1\nG1 Z0.6\nG1 X0 E2\n";
+
+    let output = insert_at_layers(src, &[LayerInsertion::new(LayerSelector::Z(0.6), "M600")]).unwrap();
+
+    assert_eq!(output, "G28\nG1 Z0.3\nG1 X10 E1\nM600\nG1 Z0.6\nG1 X0 E2\n");
+}
+
+#[test]
+fn resume_from_layer() {
+    let src = "\
+M140 S60
+M104 S210
+M190 S60
+M109 S210
+G28
+M83
+M106 S200
+;LAYER:0
+G1 Z0.2 F600
+G1 X10 Y5 E1 F1200
+;LAYER:1
+G1 Z0.4
+G1 X0 E1
+";
+
+    let output = resume_program(src, ResumeFrom::Layer(LayerSelector::Number(1)), &ResumeOptions::default())
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(output, "\
+; Resuming from line 11
+G21
+M140 S60
+M104 T0 S210
+M190 S60
+M109 T0 S210
+G92 Z0.2
+G91
+G0 Z2
+G90
+G28 X Y
+T0
+M106 P0 S200
+M83
+G92 E1
+G0 X10 Y5
+G0 Z0.2
+G1 F1200
+;LAYER:1
+G1 Z0.4
+G1 X0 E1
+");
+
+    let output = resume_program(src, ResumeFrom::Line(13), &ResumeOptions::default()).unwrap().unwrap();
+    assert!(output.contains("G0 Z0.4\nG1 F1200\nG1 X0 E1\n"));
+
+    assert_eq!(resume_program(src, ResumeFrom::Line(100), &ResumeOptions::default()).unwrap(), None);
+    assert_eq!(
+        resume_program(src, ResumeFrom::Layer(LayerSelector::Index(2)), &ResumeOptions::default()).unwrap(),
+        None,
+    );
+}
+
+const PRUSA_OBJECTS: &str = "\
+G90
+M82
+G1 Z0.2 F600
+; printing object cube id:0 copy 0
+G1 X10 Y10 F3000
+G1 X20 E1 F1200
+; stop printing object cube id:0 copy 0
+; printing object cylinder id:1 copy 0
+G1 X30 Y30 F3000
+G1 X40 E2 F1200
+; stop printing object cylinder id:1 copy 0
+G92 E0
+G1 Z0.4 F600
+; printing object cube id:0 copy 0
+G1 X10 Y10 F3000
+G1 X20 E1 F1200
+; stop printing object cube id:0 copy 0
+";
+
+#[test]
+fn find_print_objects() {
+    let objects = print_objects(PRUSA_OBJECTS).unwrap();
+
+    assert_eq!(objects.len(), 2);
+    assert_eq!(objects[0].id, 0);
+    assert_eq!(objects[0].name, "cube id:0 copy 0");
+    assert_eq!(objects[0].sections, vec![4..8, 14..18]);
+    assert_eq!(objects[1].id, 1);
+    assert_eq!(objects[1].name, "cylinder id:1 copy 0");
+    assert_eq!(objects[1].sections.len(), 1);
+    assert_eq!(objects[1].sections[0], 8..12);
+
+    let cura = ";LAYER:0\n;MESH:a.stl\nG1 X1 E1\n;MESH:b.stl\nG1 X2 E2\n;MESH:NONMESH\nG0 Z1\n";
+    let sections = print_objects(cura).unwrap()
+        .into_iter()
+        .flat_map(|object| {
+            let id = object.id;
+            object.sections.into_iter().map(move |lines| (id, lines))
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(sections, vec![(0, 2..4), (1, 4..7)]);
+}
+
+#[test]
+fn label_objects_for_marlin_and_klipper() {
+    let src = "G28\n;MESH:a b.stl\nG1 X1 E1\n;MESH:NONMESH\nG0 Z1\n;MESH:c.stl\nG1 X2 E2\n";
+
+    assert_eq!(label_objects(src, ObjectLabels::Marlin).unwrap(), "\
+M486 T2
+G28
+;MESH:a b.stl
+M486 S0
+G1 X1 E1
+M486 S-1
+;MESH:NONMESH
+G0 Z1
+;MESH:c.stl
+M486 S1
+G1 X2 E2
+M486 S-1
+");
+
+    let klipper = label_objects(src, ObjectLabels::Klipper).unwrap();
+
+    assert_eq!(klipper, "\
+EXCLUDE_OBJECT_DEFINE NAME=a_b.stl
+EXCLUDE_OBJECT_DEFINE NAME=c.stl
+G28
+;MESH:a b.stl
+EXCLUDE_OBJECT_START NAME=a_b.stl
+G1 X1 E1
+EXCLUDE_OBJECT_END NAME=a_b.stl
+;MESH:NONMESH
+G0 Z1
+;MESH:c.stl
+EXCLUDE_OBJECT_START NAME=c.stl
+G1 X2 E2
+EXCLUDE_OBJECT_END NAME=c.stl
+");
+
+    // Labelled files are left alone and the labels are recognized as objects
+    assert_eq!(label_objects(&klipper, ObjectLabels::Marlin).unwrap(), klipper);
+    assert_eq!(print_objects(&klipper).unwrap().len(), 2);
+
+    // Labelled files can be analysed like any other
+    let limits = MotionLimits::default();
+    assert_eq!(
+        estimate_print_time(&klipper, &limits).unwrap().total,
+        estimate_print_time(src, &limits).unwrap().total,
+    );
+}
+
+#[test]
+fn objects_with_non_ascii_comments() {
+    let src = "\
+;MESH:cube.stl
+(\u{e9}\u{e9})
+; \u{e9}t\u{e9}
+G1 X1 E1
+;MESH:NONMESH
+";
+
+    let objects = print_objects(src).unwrap();
+
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].sections, vec![1..6]);
+
+    let labelled = label_objects(src, ObjectLabels::Marlin).unwrap();
+    assert!(labelled.contains("(\u{e9}\u{e9})\n; \u{e9}t\u{e9}\nG1 X1 E1\nM486 S-1\n"));
+
+    // Invalid lines are errors, as for other transformations
+    assert!(print_objects("M4\u{e9}\n").is_err());
+}
+
+#[test]
+fn remove_object() {
+    let output = remove_objects(PRUSA_OBJECTS, &[0]).unwrap();
+
+    assert_eq!(output, "\
+G90
+M82
+G1 Z0.2 F600
+; printing object cube id:0 copy 0
+G0 X20 Y10 Z0.2
+G92 E1
+G1 F1200
+; stop printing object cube id:0 copy 0
+; printing object cylinder id:1 copy 0
+G1 X30 Y30 F3000
+G1 X40 E2 F1200
+; stop printing object cylinder id:1 copy 0
+G92 E0
+G1 Z0.4 F600
+; printing object cube id:0 copy 0
+G0 X20 Y10 Z0.4
+G92 E1
+G1 F1200
+; stop printing object cube id:0 copy 0
+");
+
+    // Klipper macros are kept and the labels end the removed sections
+    let src = "\
+EXCLUDE_OBJECT_START NAME=a
+M83
+G1 X5 E2 F1000
+EXCLUDE_OBJECT_END NAME=a
+G1 X6 E1
+";
+
+    assert_eq!(remove_objects(src, &[0]).unwrap(), "\
+EXCLUDE_OBJECT_START NAME=a
+M83
+G0 X5 Y0 Z0
+G1 F1000
+EXCLUDE_OBJECT_END NAME=a
+G1 X6 E1
+");
+
+    // The primes of removed objects are kept so the next object is not under-extruded
+    let src = "\
+M83
+G1 X1 E1 F1000
+G1 E-1
+;MESH:b
+G1 E1
+G1 X5 E2
+;MESH:c
+G1 X6 E1
+";
+    let output = remove_objects(src, &[0]).unwrap();
+
+    assert!(output.contains(";MESH:b\nG0 X5 Y0 Z0\nG1 E1\nG1 F1000\n;MESH:c\n"), "{}", output);
+
+    let src = "\
+M82
+G1 X1 E1 F1000
+G1 E0
+;MESH:b
+G1 E1
+G1 X5 E3
+;MESH:c
+G1 X6 E4
+";
+    let output = remove_objects(src, &[0]).unwrap();
+
+    assert!(output.contains(";MESH:b\nG0 X5 Y0 Z0\nG92 E2\nG1 E3\nG1 F1000\n;MESH:c\n"), "{}", output);
+}
+
+#[test]
+fn temperature_tower() {
+    let src = "\
+M104 S215
+;LAYER:0
+G1 Z0.2 F600
+G1 X10 E1
+M104 S205
+;LAYER:1
+G1 Z0.4
+G1 X0 E2
+;LAYER:2
+G1 Z0.6
+G1 X10 E3
+";
+
+    let sweep = ParameterSweep::tower(CalibrationParameter::HotendTemperature, 0.2, 0.2, 220.0, -5.0, 3);
+    let output = apply_parameter_sweep(src, &sweep).unwrap();
+
+    assert_eq!(output, "\
+M104 S215
+M104 S220
+;LAYER:0
+G1 Z0.2 F600
+G1 X10 E1
+M104 S215
+;LAYER:1
+G1 Z0.4
+G1 X0 E2
+M104 S210
+;LAYER:2
+G1 Z0.6
+G1 X10 E3
+");
+
+    // Waiting for a temperature also sets it
+    let output = apply_parameter_sweep(&src.replace("M104 S205", "M109 S205"), &sweep).unwrap();
+    assert!(output.contains("G1 X10 E1\nM104 S215\n;LAYER:1\n"));
+    assert!(!output.contains("M109"));
+
+    let sweep = ParameterSweep::new(CalibrationParameter::PressureAdvance)
+        .with_step(LayerSelector::Number(2), 0.04)
+        .with_step(LayerSelector::Number(9), 0.06);
+    let output = apply_parameter_sweep(src, &sweep).unwrap();
+
+    assert!(output.contains("G1 X0 E2\nSET_PRESSURE_ADVANCE ADVANCE=0.04\n;LAYER:2\n"));
+    assert!(!output.contains("0.06"));
+    // Other parameters are left alone
+    assert!(output.contains("M104 S205\n"));
+}
+
+#[test]
+fn pressure_advance_tower_with_klipper_macros() {
+    let src = "\
+PRINT_START BED=60 EXTRUDER=215
+SET_PRESSURE_ADVANCE ADVANCE=0.02
+;LAYER:0
+G1 Z0.2 F600
+G1 X10 E1
+;LAYER:1
+SET_PRESSURE_ADVANCE ADVANCE=0.03 ; from the filament profile
+G1 Z0.4
+G1 X0 E2
+PRINT_END
+";
+
+    let sweep = ParameterSweep::new(CalibrationParameter::PressureAdvance)
+        .with_step(LayerSelector::Number(0), 0.04)
+        .with_step(LayerSelector::Number(1), 0.05);
+    let output = apply_parameter_sweep(src, &sweep).unwrap();
+
+    assert_eq!(output, "\
+PRINT_START BED=60 EXTRUDER=215
+SET_PRESSURE_ADVANCE ADVANCE=0.02
+SET_PRESSURE_ADVANCE ADVANCE=0.04
+;LAYER:0
+G1 Z0.2 F600
+G1 X10 E1
+SET_PRESSURE_ADVANCE ADVANCE=0.05
+;LAYER:1
+G1 Z0.4
+G1 X0 E2
+PRINT_END
+");
+
+    // The output can be processed further
+    assert_eq!(insert_at_layers(&output, &[]).unwrap(), output);
+}